@@ -0,0 +1,87 @@
+use crate::config::{AuditConfig, AuditDestination};
+use crate::error::AppError;
+use crate::models::RecognitionResult;
+use crate::store::GalleryStore;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+/// One recognized face. Deliberately excludes embeddings and image data.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub name: String,
+    pub similarity: f32,
+    pub bbox: Option<[f32; 4]>,
+}
+
+/// The current time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Append-only log of recognition outcomes, written to a JSON lines file or a
+/// table of the gallery store.
+pub enum AuditLog {
+    File(tokio::sync::Mutex<tokio::fs::File>),
+    Database { table: String },
+}
+
+impl AuditLog {
+    /// Open the configured destination; the file is created if missing and only ever appended to
+    pub async fn open(config: &AuditConfig) -> anyhow::Result<Self> {
+        match config.destination {
+            AuditDestination::File => {
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&config.path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Could not open audit log {:?}: {}", config.path, e))?;
+                tracing::info!("Audit logging to {:?}", config.path);
+                Ok(AuditLog::File(tokio::sync::Mutex::new(file)))
+            }
+            AuditDestination::Database => {
+                tracing::info!("Audit logging to table {:?}", config.table);
+                Ok(AuditLog::Database { table: config.table.clone() })
+            }
+        }
+    }
+
+    /// Record the outcome of one recognition request, one entry per face. Faces that
+    /// weren't accepted as a match are logged as `unknown_label`, whatever name was reported.
+    pub async fn record(&self, store: &dyn GalleryStore, results: &[RecognitionResult], unknown_label: &str) -> Result<(), AppError> {
+        if results.is_empty() {
+            return Ok(());
+        }
+        let timestamp = now_millis();
+        let entries: Vec<AuditEntry> = results
+            .iter()
+            .map(|result| AuditEntry {
+                timestamp,
+                name: if result.is_match { result.name.clone() } else { unknown_label.to_string() },
+                similarity: result.similarity,
+                bbox: result.bbox,
+            })
+            .collect();
+
+        match self {
+            AuditLog::File(file) => {
+                let mut lines = String::new();
+                for entry in &entries {
+                    lines.push_str(&serde_json::to_string(entry).map_err(anyhow::Error::from)?);
+                    lines.push('\n');
+                }
+                let mut file = file.lock().await;
+                file.write_all(lines.as_bytes()).await.map_err(anyhow::Error::from)?;
+                file.flush().await.map_err(anyhow::Error::from)?;
+            }
+            AuditLog::Database { table } => store.append_audit(table, &entries).await?,
+        }
+        Ok(())
+    }
+}
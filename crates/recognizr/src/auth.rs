@@ -0,0 +1,73 @@
+use crate::error::AppError;
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+/// Middleware guarding the `/admin` routes with the configured admin token.
+pub async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(expected) = state.config.admin.token.as_deref() else {
+        return Err(AppError::Forbidden("Admin endpoints are disabled (no admin token configured)".to_string()));
+    };
+
+    match bearer_token(request.headers()) {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(next.run(request).await),
+        _ => Err(AppError::Unauthorized("Invalid or missing admin token".to_string())),
+    }
+}
+
+/// Middleware requiring one of the configured API keys, sent either as a bearer token or
+/// in the `X-API-Key` header. Passes every request through when no keys are configured.
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let keys = &state.config.auth.api_keys;
+    if keys.is_empty() {
+        return Ok(next.run(request).await);
+    }
+
+    match provided_api_key(request.headers()) {
+        Some(provided) if keys.iter().any(|key| constant_time_eq(provided.as_bytes(), key.as_bytes())) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(AppError::Unauthorized("Invalid or missing API key".to_string())),
+    }
+}
+
+/// The API key a client sent, from either the bearer token or the `X-API-Key` header
+pub fn provided_api_key(headers: &HeaderMap) -> Option<&str> {
+    bearer_token(headers).or_else(|| api_key_header(headers))
+}
+
+/// Extract the key from an `X-API-Key` header
+fn api_key_header(headers: &HeaderMap) -> Option<&str> {
+    headers.get("x-api-key")?.to_str().ok().map(str::trim)
+}
+
+/// Extract the token from an `Authorization: Bearer <token>` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Compare two secrets without short-circuiting on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,138 @@
+use crate::config::RecognitionCacheConfig;
+use crate::models::RecognitionResult;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// What a cached recognition depends on besides the gallery: the image, identified by
+/// its perceptual hash and dimensions (results carry boxes in image coordinates), and
+/// the query parameters that change the outcome
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub image_hash: u64,
+    pub dimensions: (u32, u32),
+    pub group: Option<String>,
+    /// `f32::to_bits` of the `threshold` and `recognize_min_score` overrides
+    pub threshold: Option<u32>,
+    pub recognize_min_score: Option<u32>,
+}
+
+/// LRU cache of recent `/recognize` results, so retried or duplicated uploads skip the
+/// models and the gallery lookups. Results depend on the gallery, so every change to
+/// it invalidates the whole cache.
+pub struct RecognitionCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<CacheKey, Entry>,
+    /// Bumped on every invalidation; results computed against an older gallery are dropped
+    generation: u64,
+    /// Monotonic counter recording the order entries were last used in
+    clock: u64,
+}
+
+struct Entry {
+    /// Exact hash of the image the results belong to, since different images can share
+    /// a perceptual hash
+    content_hash: [u8; 32],
+    results: Vec<RecognitionResult>,
+    inserted: Instant,
+    last_used: u64,
+}
+
+impl RecognitionCache {
+    pub fn new(config: &RecognitionCacheConfig) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity: config.capacity,
+            ttl: Duration::from_secs(config.ttl_secs),
+        }
+    }
+
+    /// Lock the entries. A panic while they were locked can at worst have left a stale
+    /// entry, so a poisoned lock is recovered instead of failing every later request.
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The gallery generation a recognition starts against, to pass to [`Self::insert`]
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// The cached results for `key`, unless they are older than the TTL or were computed
+    /// for a different image with the same perceptual hash
+    pub fn get(&self, key: &CacheKey, content_hash: &[u8; 32]) -> Option<Vec<RecognitionResult>> {
+        let mut inner = self.lock();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        let entry = inner.entries.get_mut(key)?;
+        if entry.inserted.elapsed() > self.ttl {
+            inner.entries.remove(key);
+            return None;
+        }
+        if entry.content_hash != *content_hash {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.results.clone())
+    }
+
+    /// Cache `results`, evicting the least recently used entry when full. Results of a
+    /// recognition that overlapped a gallery change (`generation` is stale) are discarded.
+    pub fn insert(&self, key: CacheKey, content_hash: [u8; 32], generation: u64, results: Vec<RecognitionResult>) {
+        let mut inner = self.lock();
+        if inner.generation != generation {
+            return;
+        }
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            let ttl = self.ttl;
+            inner.entries.retain(|_, entry| entry.inserted.elapsed() <= ttl);
+            if inner.entries.len() >= self.capacity {
+                let oldest = inner.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    inner.entries.remove(&oldest);
+                }
+            }
+        }
+        inner.entries.insert(key, Entry { content_hash, results, inserted: Instant::now(), last_used: clock });
+    }
+
+    /// Forget every cached result after the gallery changed
+    pub fn invalidate(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_perceptual_hash_collision_is_not_served() {
+        let cache = RecognitionCache::new(&RecognitionCacheConfig { enabled: true, capacity: 4, ttl_secs: 30 });
+        let key = CacheKey { image_hash: 0x1234, dimensions: (64, 64), group: None, threshold: None, recognize_min_score: None };
+        let results = vec![RecognitionResult {
+            name: "Alice".to_string(),
+            similarity: 0.9,
+            is_match: true,
+            bbox: None,
+            keypoints: None,
+            track_id: None,
+        }];
+        cache.insert(key.clone(), [1; 32], cache.generation(), results);
+
+        assert!(cache.get(&key, &[1; 32]).is_some());
+        assert!(cache.get(&key, &[2; 32]).is_none());
+    }
+}
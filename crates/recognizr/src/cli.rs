@@ -0,0 +1,47 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+/// Face detection and recognition API server
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to the configuration file (defaults to `config.*` in the working directory)
+    #[arg(long, env = "RECOGNIZR_CONFIG", value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Runs the API server when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Enroll a directory of labeled images, one subdirectory per person
+    /// (e.g. `gallery/Alice/*.jpg`), then exit
+    Import {
+        /// Directory whose subdirectories are named after the persons they contain
+        #[arg(long, value_name = "DIR")]
+        dir: PathBuf,
+        /// Enroll images even if they closely match an already enrolled person
+        #[arg(long)]
+        force: bool,
+        /// Gallery group to enroll the persons into (the default gallery when omitted)
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Print a configuration file with every setting at its default value, then exit
+    Config {
+        /// Print the example configuration (currently the only mode)
+        #[arg(long, required = true)]
+        example: bool,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Toml)]
+        format: ConfigFormat,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
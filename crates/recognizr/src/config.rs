@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use ort::session::Session;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use image::imageops::FilterType;
+use ndarray::Array4;
+use ort::{session::{builder::GraphOptimizationLevel, Session}, value::Value};
 use crate::error::AppError;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,6 +12,33 @@ pub struct Configuration {
     pub models: ModelsConfig,
     pub database: DatabaseConfig,
     pub server: ServerConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    #[serde(default)]
+    pub recognition: RecognitionConfig,
+    #[serde(default)]
+    pub images: ImageConfig,
+    #[serde(default)]
+    pub drawing: DrawingConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub tracking: TrackingConfig,
+    #[serde(default)]
+    pub cache: RecognitionCacheConfig,
+    /// Serve HTTPS directly instead of plain HTTP when present
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +50,73 @@ pub struct FontConfig {
 pub struct ModelsConfig {
     pub detector: DetectorConfig,
     pub recognizer: RecognizerConfig,
+    /// Dummy inferences run through both models at startup to absorb lazy
+    /// initialization costs before the first real request (0 disables)
+    #[serde(default = "default_warmup_iterations")]
+    pub warmup_iterations: u32,
+    /// Let requests run each model concurrently instead of one inference at a time
+    #[serde(default)]
+    pub concurrent_inference: bool,
+    /// Requests allowed to run inference at once; others queue for a slot. Unset is unlimited.
+    #[serde(default)]
+    pub max_concurrent_inferences: Option<usize>,
+    /// How long a request waits for an inference slot before failing with 503
+    #[serde(default = "default_inference_queue_timeout_ms")]
+    pub inference_queue_timeout_ms: u64,
+    /// Deprecated flat model paths of early configuration files, moved into
+    /// `detector.path` and `recognizer.path` when the configuration is loaded
+    #[serde(default, skip_serializing)]
+    pub detector_path: Option<PathBuf>,
+    #[serde(default, skip_serializing)]
+    pub recognizer_path: Option<PathBuf>,
+}
+
+/// What [`Configuration::load`] found, to log once logging is set up
+pub struct LoadReport {
+    file_found: bool,
+    /// Every setting with where its value came from: the environment variable, "file" or "default"
+    sources: Vec<(String, String)>,
+    warnings: Vec<String>,
+}
+
+impl LoadReport {
+    pub fn log(&self) {
+        if !self.file_found {
+            tracing::info!("No configuration file found, using defaults and RECOGNIZR_ environment variables");
+        }
+        for (setting, source) in &self.sources {
+            tracing::debug!("Setting {} from {}", setting, source);
+        }
+        for warning in &self.warnings {
+            tracing::warn!("{}", warning);
+        }
+    }
+}
+
+impl ModelsConfig {
+    /// Apply the deprecated `detector_path` / `recognizer_path` keys to the structured
+    /// sections. Everything else those files lacked (strides, input_shape, ...) is already
+    /// filled in from the defaults. Returns a deprecation warning per key found.
+    fn migrate_flat_paths(&mut self) -> Vec<&'static str> {
+        let mut warnings = Vec::new();
+        if let Some(path) = self.detector_path.take() {
+            warnings.push("models.detector_path is deprecated, set models.detector.path instead (see README, Migrating Flat Model Paths)");
+            self.detector.path = path;
+        }
+        if let Some(path) = self.recognizer_path.take() {
+            warnings.push("models.recognizer_path is deprecated, set models.recognizer.path instead (see README, Migrating Flat Model Paths)");
+            self.recognizer.path = path;
+        }
+        warnings
+    }
+}
+
+fn default_warmup_iterations() -> u32 {
+    1
+}
+
+fn default_inference_queue_timeout_ms() -> u64 {
+    10_000
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +125,127 @@ pub struct DetectorConfig {
     pub strides: Vec<i32>,
     /// Input shape for the detector model [height, width]
     pub input_shape: [u32; 2],
+    /// Anchors per feature-map location (2 for the standard SCRFD exports)
+    #[serde(default = "default_num_anchors")]
+    pub num_anchors: usize,
+    /// Longest side images are downsized to before detection, to save preprocessing time
+    /// on huge uploads. Recognition still crops from the full-resolution image.
+    #[serde(default)]
+    pub max_working_dimension: Option<u32>,
+    /// Extra detection passes over the image enlarged by these factors (e.g. `[2.0]`), tiled
+    /// into detector-sized windows, to find faces too small for the single-scale pass.
+    /// Empty (the default) detects at a single scale.
+    #[serde(default)]
+    pub pyramid_scales: Vec<f32>,
+    /// Confidence threshold used when neither the request nor `stride_thresholds` sets one
+    #[serde(default = "default_detection_threshold")]
+    pub default_threshold: f32,
+    /// Confidence thresholds for individual strides, keyed by stride (e.g. "8" = 0.5).
+    /// Strides without an entry use the default threshold.
+    #[serde(default)]
+    pub stride_thresholds: HashMap<String, f32>,
+    /// Explicit output names per stride as `[score, bbox]` or `[score, bbox, kps]`
+    /// (e.g. "8" = ["448", "451", "454"]). Replaces shape-based auto-detection
+    /// when set, and must then cover every configured stride.
+    #[serde(default)]
+    pub output_mapping: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
+    /// Memory layout of the detector input tensor
+    #[serde(default)]
+    pub input_layout: InputLayout,
+    /// Filter used to resize images to the detector input
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// CUDA device the detector runs on
+    #[serde(default)]
+    pub device_id: i32,
+    #[serde(default)]
+    pub session: SessionOptions,
+}
+
+fn default_num_anchors() -> usize {
+    2
+}
+
+fn default_detection_threshold() -> f32 {
+    0.7
+}
+
+impl DetectorConfig {
+    /// Parse the per-stride thresholds, checking that each key is a configured stride
+    pub fn parsed_stride_thresholds(&self) -> anyhow::Result<HashMap<i32, f32>> {
+        let mut thresholds = HashMap::new();
+        for (key, &threshold) in &self.stride_thresholds {
+            let stride: i32 = key.trim().parse().map_err(|_| {
+                anyhow::anyhow!("models.detector.stride_thresholds has a non-numeric stride key {:?}", key)
+            })?;
+            if !self.strides.contains(&stride) {
+                anyhow::bail!("models.detector.stride_thresholds has an entry for stride {}, which is not in strides", stride);
+            }
+            if !(threshold > 0.0 && threshold < 1.0) {
+                anyhow::bail!("models.detector.stride_thresholds.{} must be in (0, 1), got {}", stride, threshold);
+            }
+            thresholds.insert(stride, threshold);
+        }
+        Ok(thresholds)
+    }
+
+    /// Parse the manual output mapping, checking that it covers exactly the configured strides
+    pub fn parsed_output_mapping(&self) -> anyhow::Result<HashMap<i32, &[String]>> {
+        let mut mapping = HashMap::new();
+        for (key, names) in &self.output_mapping {
+            let stride: i32 = key.trim().parse().map_err(|_| {
+                anyhow::anyhow!("models.detector.output_mapping has a non-numeric stride key {:?}", key)
+            })?;
+            if !self.strides.contains(&stride) {
+                anyhow::bail!("models.detector.output_mapping has an entry for stride {}, which is not in strides", stride);
+            }
+            if !(2..=3).contains(&names.len()) {
+                anyhow::bail!(
+                    "models.detector.output_mapping.{} must list [score, bbox] or [score, bbox, kps], got {} names",
+                    stride,
+                    names.len()
+                );
+            }
+            mapping.insert(stride, names.as_slice());
+        }
+        if !mapping.is_empty() {
+            if let Some(stride) = self.strides.iter().find(|s| !mapping.contains_key(s)) {
+                anyhow::bail!("models.detector.output_mapping is missing an entry for stride {}", stride);
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Resolve the manual output mapping against the model's output names.
+    /// Returns `None` when no manual mapping is configured.
+    pub fn resolve_output_mapping(&self, output_names: &[String]) -> anyhow::Result<Option<StrideOutputMapping>> {
+        let parsed = self.parsed_output_mapping()?;
+        if parsed.is_empty() {
+            return Ok(None);
+        }
+
+        let index_of = |stride: i32, name: &str| {
+            output_names.iter().position(|n| n == name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "models.detector.output_mapping.{} references unknown output {:?} (model outputs: {:?})",
+                    stride,
+                    name,
+                    output_names
+                )
+            })
+        };
+
+        let mut mapping = StrideOutputMapping::new();
+        for (stride, names) in parsed {
+            let score_idx = index_of(stride, &names[0])?;
+            let bbox_idx = index_of(stride, &names[1])?;
+            let kps_idx = names.get(2).map(|name| index_of(stride, name)).transpose()?;
+            mapping.insert(stride, (score_idx, bbox_idx, kps_idx));
+        }
+        Ok(Some(mapping))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +253,235 @@ pub struct RecognizerConfig {
     pub path: PathBuf,
     /// Input size for the recognizer model (square input)
     pub input_size: u32,
+    /// Margin added around the detected box before cropping, as a fraction of the
+    /// box size (0.2 grows it by 20%). 0 crops the box exactly.
+    #[serde(default)]
+    pub crop_padding: f32,
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
+    /// Memory layout of the recognizer input tensor
+    #[serde(default)]
+    pub input_layout: InputLayout,
+    /// Filter used to resize face crops to the recognizer input
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Also embed the horizontally mirrored crop and combine both embeddings
+    /// (test-time augmentation). More accurate, but doubles the recognizer cost.
+    #[serde(default)]
+    pub flip_tta: bool,
+    /// Unsharp mask applied to face crops that are enlarged to the input size, which can
+    /// help with low-resolution faces. Off when unset.
+    #[serde(default)]
+    pub sharpen: Option<SharpenConfig>,
+    /// L2-normalize embeddings before storing and comparing them. Disable for raw
+    /// embeddings; cosine similarity is unaffected either way.
+    #[serde(default = "default_normalize_embeddings")]
+    pub normalize_embeddings: bool,
+    /// CUDA device the recognizer runs on
+    #[serde(default)]
+    pub device_id: i32,
+    #[serde(default)]
+    pub session: SessionOptions,
+}
+
+fn default_normalize_embeddings() -> bool {
+    true
+}
+
+/// Unsharp mask parameters, as for `image::imageops::unsharpen`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SharpenConfig {
+    /// Standard deviation of the blur the crop is sharpened against, in pixels
+    #[serde(default = "default_sharpen_sigma")]
+    pub sigma: f32,
+    /// Minimum brightness difference, 0-255, for a pixel to be sharpened; higher values
+    /// leave flat areas (and their noise) alone
+    #[serde(default)]
+    pub threshold: i32,
+}
+
+fn default_sharpen_sigma() -> f32 {
+    1.0
+}
+
+/// ONNX Runtime session tuning, mostly relevant for CPU inference
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionOptions {
+    pub graph_optimization_level: OptimizationLevel,
+    /// Threads used within a single operator; unset uses ONNX Runtime's default
+    /// (one per physical core)
+    pub intra_threads: Option<usize>,
+    /// Threads used to run independent operators in parallel; unset uses ONNX
+    /// Runtime's default. Setting it enables parallel execution mode.
+    pub inter_threads: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizationLevel {
+    Disable,
+    Basic,
+    Extended,
+    #[default]
+    All,
+}
+
+impl OptimizationLevel {
+    pub fn ort_level(self) -> GraphOptimizationLevel {
+        match self {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+            OptimizationLevel::All => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+/// Resampling filter used when resizing images for a model. Matching the filter the
+/// model was trained with can measurably improve accuracy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    Nearest,
+    /// Linear interpolation ("triangle" in the `image` crate)
+    #[default]
+    #[serde(alias = "triangle")]
+    Bilinear,
+    /// Catmull-Rom cubic interpolation
+    #[serde(alias = "catmullrom")]
+    Bicubic,
+    Gaussian,
+    Lanczos3,
+}
+
+impl Interpolation {
+    pub fn filter_type(self) -> FilterType {
+        match self {
+            Interpolation::Nearest => FilterType::Nearest,
+            Interpolation::Bilinear => FilterType::Triangle,
+            Interpolation::Bicubic => FilterType::CatmullRom,
+            Interpolation::Gaussian => FilterType::Gaussian,
+            Interpolation::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Pixel normalization applied when building a model's input tensor:
+/// `(pixel - mean) / std`, per tensor channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NormalizationConfig {
+    /// Per-channel mean, in tensor channel order
+    #[serde(default = "default_normalization_mean")]
+    pub mean: [f32; 3],
+    /// Per-channel standard deviation, in tensor channel order
+    #[serde(default = "default_normalization_std")]
+    pub std: [f32; 3],
+    /// Channel order the model expects
+    #[serde(default)]
+    pub channel_order: ChannelOrder,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    #[default]
+    Bgr,
+    Rgb,
+}
+
+impl ChannelOrder {
+    /// Source RGB pixel index for each tensor channel
+    pub fn source_indices(self) -> [usize; 3] {
+        match self {
+            ChannelOrder::Bgr => [2, 1, 0],
+            ChannelOrder::Rgb => [0, 1, 2],
+        }
+    }
+}
+
+/// Memory layout of a model's image input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputLayout {
+    /// `(batch, channels, height, width)`, as exported from PyTorch
+    #[default]
+    Nchw,
+    /// `(batch, height, width, channels)`, as exported from TensorFlow
+    Nhwc,
+}
+
+impl InputLayout {
+    fn name(self) -> &'static str {
+        match self {
+            InputLayout::Nchw => "nchw",
+            InputLayout::Nhwc => "nhwc",
+        }
+    }
+
+    fn channel_axis(self) -> usize {
+        match self {
+            InputLayout::Nchw => 1,
+            InputLayout::Nhwc => 3,
+        }
+    }
+
+    /// Rearranges an NCHW tensor, as built by preprocessing, into this layout
+    pub fn arrange(self, tensor: Array4<f32>) -> Array4<f32> {
+        match self {
+            InputLayout::Nchw => tensor,
+            InputLayout::Nhwc => tensor.permuted_axes([0, 2, 3, 1]).as_standard_layout().into_owned(),
+        }
+    }
+
+    /// Checks this layout against the input dimensions a model declares, if it declares
+    /// any: the input must have rank 4 with 3 channels (or a dynamic size) on the
+    /// channel axis
+    fn check_declared(self, model: &str, dims: Option<&Vec<i64>>) -> Result<(), AppError> {
+        let Some(dims) = dims else {
+            return Ok(());
+        };
+        if dims.len() != 4 {
+            return Err(AppError::BadRequest(format!(
+                "The {} input has rank {} ({:?}), but image inputs need rank 4", model, dims.len(), dims
+            )));
+        }
+        let fits = |layout: InputLayout| matches!(dims[layout.channel_axis()], 3 | ..0);
+        if !fits(self) {
+            let other = match self {
+                InputLayout::Nchw => InputLayout::Nhwc,
+                InputLayout::Nhwc => InputLayout::Nchw,
+            };
+            let hint = if fits(other) {
+                format!("; set models.{}.input_layout = \"{}\"", model, other.name())
+            } else {
+                String::new()
+            };
+            return Err(AppError::BadRequest(format!(
+                "The {} input {:?} has no 3-channel axis where the {} layout expects it{}",
+                model, dims, self.name(), hint
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn default_normalization_mean() -> [f32; 3] {
+    [127.5; 3]
+}
+
+fn default_normalization_std() -> [f32; 3] {
+    [127.5; 3]
+}
+
+impl Default for NormalizationConfig {
+    fn default() -> Self {
+        Self {
+            mean: default_normalization_mean(),
+            std: default_normalization_std(),
+            channel_order: ChannelOrder::default(),
+        }
+    }
 }
 
 
@@ -46,27 +493,806 @@ pub struct DatabaseConfig {
     pub password: String,
     pub namespace: String,
     pub database: String,
+    /// Table holding the enrolled persons. Separate tables keep several galleries
+    /// (e.g. "employees" and "visitors") apart within one database.
+    #[serde(default = "default_person_table")]
+    pub person_table: String,
+    /// Connection attempts at startup before giving up, so the app can start
+    /// alongside the database (1 disables retrying)
+    #[serde(default = "default_connect_max_attempts")]
+    pub connect_max_attempts: u32,
+    /// Delay before the first retry; doubles after each failed attempt
+    #[serde(default = "default_connect_initial_backoff_ms")]
+    pub connect_initial_backoff_ms: u64,
+    /// Upper bound for the delay between attempts
+    #[serde(default = "default_connect_max_backoff_ms")]
+    pub connect_max_backoff_ms: u64,
+}
+
+fn default_person_table() -> String {
+    "person".to_string()
+}
+
+fn default_connect_max_attempts() -> u32 {
+    10
+}
+
+fn default_connect_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_connect_max_backoff_ms() -> u64 {
+    10_000
+}
+
+impl DatabaseConfig {
+    pub fn url(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Requests still running after this many seconds are aborted with 504 (unset: no limit).
+    /// Admin endpoints are exempt since re-embedding a large gallery takes a while.
+    #[serde(default)]
+    pub request_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// `tracing` filter directives such as "recognizr=info,tower_http=warn", used when
+    /// `RUST_LOG` isn't set. Falls back to "recognizr=debug,tower_http=debug".
+    #[serde(default)]
+    pub filter: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, for log aggregators
+    Json,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Bearer token required by the `/admin` endpoints. They are disabled when unset.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Accepted API keys. Authentication is disabled when the list is empty.
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Sustained requests per second allowed per client (API key with `auth.api_keys` set, otherwise IP)
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: f64,
+    /// Number of requests a client may burst above the sustained rate
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+}
+
+fn default_requests_per_second() -> f64 {
+    5.0
+}
+
+fn default_burst() -> u32 {
+    10
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            requests_per_second: default_requests_per_second(),
+            burst: default_burst(),
+        }
+    }
+}
+
+/// Face tracking across the frames of a video stream (`/recognize?track=<stream>`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackingConfig {
+    /// Minimum IoU between a face and its box in the previous frame to continue a track
+    #[serde(default = "default_track_iou_threshold")]
+    pub iou_threshold: f32,
+    /// Frames after which a tracked face is recognized again rather than reusing its identity
+    #[serde(default = "default_requery_interval")]
+    pub requery_interval: u32,
+    /// Number of recent recognitions per track that its identity is voted from
+    #[serde(default = "default_track_window")]
+    pub window: usize,
+    /// Consecutive frames a face may go undetected before its track is dropped
+    #[serde(default = "default_max_missed_frames")]
+    pub max_missed_frames: u32,
+    /// Streams without a frame for this long are forgotten
+    #[serde(default = "default_stream_idle_timeout_secs")]
+    pub stream_idle_timeout_secs: u64,
+}
+
+fn default_track_iou_threshold() -> f32 {
+    0.3
+}
+
+fn default_requery_interval() -> u32 {
+    10
+}
+
+fn default_track_window() -> usize {
+    5
+}
+
+fn default_max_missed_frames() -> u32 {
+    5
+}
+
+fn default_stream_idle_timeout_secs() -> u64 {
+    60
+}
+
+impl Default for TrackingConfig {
+    fn default() -> Self {
+        Self {
+            iou_threshold: default_track_iou_threshold(),
+            requery_interval: default_requery_interval(),
+            window: default_track_window(),
+            max_missed_frames: default_max_missed_frames(),
+            stream_idle_timeout_secs: default_stream_idle_timeout_secs(),
+        }
+    }
+}
+
+/// Cache of recent `/recognize` results keyed by a perceptual hash of the image. A hit
+/// is only served for exactly the same pixels, so colliding images are recognized afresh.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecognitionCacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Maximum number of cached results; the least recently used is evicted first
+    #[serde(default = "default_cache_capacity")]
+    pub capacity: usize,
+    /// Seconds a cached result is served for
+    #[serde(default = "default_cache_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+fn default_cache_capacity() -> usize {
+    256
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    30
+}
+
+impl Default for RecognitionCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: default_cache_capacity(),
+            ttl_secs: default_cache_ttl_secs(),
+        }
+    }
+}
+
+/// Where enrolled persons are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    /// Database file used by the SQLite backend, created if missing
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: PathBuf,
+    /// Run one gallery lookup at startup so the first recognition request doesn't pay
+    /// for the initial full scan; otherwise the gallery is read lazily on first use
+    #[serde(default)]
+    pub prewarm: bool,
+}
+
+fn default_sqlite_path() -> PathBuf {
+    PathBuf::from("recognizr.db")
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            sqlite_path: default_sqlite_path(),
+            prewarm: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// SurrealDB, configured in `[database]`
+    #[default]
+    Surreal,
+    /// Embedded SQLite file at `sqlite_path`
+    Sqlite,
+    /// In process memory; nothing survives a restart (tests and demos)
+    Memory,
+}
+
+/// Append-only record of recognition outcomes. Entries hold the timestamp, matched
+/// name, similarity and bounding box; embeddings and images are never logged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub destination: AuditDestination,
+    /// JSON lines file appended to when `destination = "file"`
+    #[serde(default = "default_audit_path")]
+    pub path: PathBuf,
+    /// SurrealDB table written to when `destination = "database"`
+    #[serde(default = "default_audit_table")]
+    pub table: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditDestination {
+    #[default]
+    File,
+    Database,
+}
+
+fn default_audit_path() -> PathBuf {
+    PathBuf::from("audit.log")
+}
+
+fn default_audit_table() -> String {
+    "audit".to_string()
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            destination: AuditDestination::default(),
+            path: default_audit_path(),
+            table: default_audit_table(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, e.g. "http://localhost:5173".
+    /// Any origin is allowed when the list is empty (development mode).
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecognitionConfig {
+    /// Lowest similarity at which the best candidate is accepted as a match
+    #[serde(default = "default_match_threshold")]
+    pub match_threshold: f32,
+    /// Report the best candidate's name in `/recognize` even when it isn't accepted as a
+    /// match (`is_match: false`), instead of `unknown_label`
+    #[serde(default)]
+    pub report_best_candidate: bool,
+    /// Minimum lead the best match must have over the runner-up to be accepted;
+    /// closer calls are reported as unknown (0 disables the check)
+    #[serde(default)]
+    pub match_margin: f32,
+    /// Largest average cosine distance at which `/cluster` merges two groups of faces
+    #[serde(default = "default_cluster_distance_threshold")]
+    pub cluster_distance_threshold: f32,
+    /// Enrollment is rejected with 409 when the face matches an enrolled person with a
+    /// higher similarity than this, unless `?force=true` is passed
+    #[serde(default = "default_duplicate_threshold")]
+    pub duplicate_threshold: f32,
+    /// Detections scoring below this are reported by `/recognize` as unknown without
+    /// being embedded or looked up (0 recognizes every detection)
+    #[serde(default)]
+    pub recognize_min_score: f32,
+    /// Name reported for faces that don't match anyone, in `/recognize` and on debug images
+    #[serde(default = "default_unknown_label")]
+    pub unknown_label: String,
+    /// Leave unrecognized faces out of `/recognize` responses entirely
+    #[serde(default)]
+    pub omit_unknown: bool,
+    /// Groups accepted by `?group=`, which splits the gallery into isolated galleries
+    /// (e.g. one per customer). Any group name is accepted when the list is empty.
+    #[serde(default)]
+    pub allowed_groups: Vec<String>,
+    /// Decimal places similarities are rounded to in `/recognize` responses, e.g. 3 for
+    /// 0.612 instead of 0.6123199. Thresholds are always compared at full precision.
+    #[serde(default)]
+    pub similarity_decimals: Option<u32>,
+    /// Report every face as unknown without running the recognizer while no one is
+    /// enrolled. Off by default: it goes by the gallery size cached by this instance, so
+    /// persons enrolled through another instance go unrecognized until `/admin/reload`.
+    #[serde(default)]
+    pub skip_empty_gallery: bool,
+    /// Reject enrollment with 422 when the head is turned or tilted further than
+    /// `max_enrollment_yaw` / `max_enrollment_roll` degrees, going by the estimated pose
+    #[serde(default)]
+    pub require_frontal_enrollment: bool,
+    #[serde(default = "default_max_enrollment_yaw")]
+    pub max_enrollment_yaw: f32,
+    #[serde(default = "default_max_enrollment_roll")]
+    pub max_enrollment_roll: f32,
+}
+
+fn default_max_enrollment_yaw() -> f32 {
+    25.0
+}
+
+fn default_max_enrollment_roll() -> f32 {
+    20.0
+}
+
+fn default_match_threshold() -> f32 {
+    0.45
+}
+
+fn default_unknown_label() -> String {
+    "Unknown".to_string()
+}
+
+fn default_cluster_distance_threshold() -> f32 {
+    0.55
+}
+
+fn default_duplicate_threshold() -> f32 {
+    0.75
+}
+
+impl Default for RecognitionConfig {
+    fn default() -> Self {
+        Self {
+            match_threshold: default_match_threshold(),
+            report_best_candidate: false,
+            match_margin: 0.0,
+            cluster_distance_threshold: default_cluster_distance_threshold(),
+            duplicate_threshold: default_duplicate_threshold(),
+            recognize_min_score: 0.0,
+            unknown_label: default_unknown_label(),
+            omit_unknown: false,
+            allowed_groups: Vec::new(),
+            similarity_decimals: None,
+            skip_empty_gallery: false,
+            require_frontal_enrollment: false,
+            max_enrollment_yaw: default_max_enrollment_yaw(),
+            max_enrollment_roll: default_max_enrollment_roll(),
+        }
+    }
+}
+
+/// Limits applied to uploaded images before they reach the models
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageConfig {
+    /// Formats accepted for upload, identified by their magic bytes
+    #[serde(default = "default_allowed_formats")]
+    pub allowed_formats: Vec<UploadFormat>,
+    /// Largest decoded area (width * height) accepted, checked from the image header
+    /// before decoding so small, highly compressed files can't expand into huge bitmaps
+    #[serde(default = "default_max_pixels")]
+    pub max_pixels: u64,
+    /// Largest accepted image file; the request body limit is derived from it
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+}
+
+fn default_allowed_formats() -> Vec<UploadFormat> {
+    vec![UploadFormat::Jpeg, UploadFormat::Png, UploadFormat::Webp]
+}
+
+fn default_max_pixels() -> u64 {
+    8192 * 8192
+}
+
+fn default_max_upload_bytes() -> usize {
+    15 * 1024 * 1024
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            allowed_formats: default_allowed_formats(),
+            max_pixels: default_max_pixels(),
+            max_upload_bytes: default_max_upload_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    Bmp,
+    Tiff,
+}
+
+impl UploadFormat {
+    pub fn image_format(self) -> image::ImageFormat {
+        match self {
+            UploadFormat::Jpeg => image::ImageFormat::Jpeg,
+            UploadFormat::Png => image::ImageFormat::Png,
+            UploadFormat::Webp => image::ImageFormat::WebP,
+            UploadFormat::Gif => image::ImageFormat::Gif,
+            UploadFormat::Bmp => image::ImageFormat::Bmp,
+            UploadFormat::Tiff => image::ImageFormat::Tiff,
+        }
+    }
+}
+
+/// How faces are drawn on `/debug/detector` and `/recognize?annotate=true` images
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrawingConfig {
+    #[serde(default)]
+    pub color_mode: ColorMode,
+    /// RGB color of unknown faces and of similarity 0 in `gradient` mode
+    #[serde(default = "default_gradient_low")]
+    pub gradient_low: [u8; 3],
+    /// RGB color of similarity 1 in `gradient` mode
+    #[serde(default = "default_gradient_high")]
+    pub gradient_high: [u8; 3],
+    /// Add the detection confidence to each label, after the similarity
+    #[serde(default)]
+    pub show_detection_score: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Blue boxes for recognized faces, red for unknown ones
+    #[default]
+    Uniform,
+    /// Box color interpolated between `gradient_low` and `gradient_high` by similarity
+    Gradient,
+}
+
+fn default_gradient_low() -> [u8; 3] {
+    [255, 0, 0]
+}
+
+fn default_gradient_high() -> [u8; 3] {
+    [0, 255, 0]
+}
+
+impl Default for DrawingConfig {
+    fn default() -> Self {
+        Self {
+            color_mode: ColorMode::default(),
+            gradient_low: default_gradient_low(),
+            gradient_high: default_gradient_high(),
+            show_detection_score: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM-encoded certificate chain
+    pub cert_path: PathBuf,
+    /// PEM-encoded private key
+    pub key_path: PathBuf,
 }
 
 impl Configuration {
-    pub fn load() -> anyhow::Result<Self> {
+    /// The default configuration rendered as a complete configuration file, listing every
+    /// setting this build understands. Optional sections without a default (such as
+    /// `[tls]`) are omitted.
+    pub fn example(format: crate::cli::ConfigFormat) -> anyhow::Result<String> {
+        let example = Configuration::default();
+        Ok(match format {
+            crate::cli::ConfigFormat::Toml => format!(
+                "# Example configuration for recognizr {}\n# Every setting is shown at its default value\n\n{}",
+                env!("CARGO_PKG_VERSION"),
+                toml::to_string_pretty(&example)?
+            ),
+            crate::cli::ConfigFormat::Json => serde_json::to_string_pretty(&example)? + "\n",
+        })
+    }
+
+    /// Load the configuration from `path`, or from `config.*` in the working directory
+    /// when no path is given, then apply `RECOGNIZR_` environment overrides. Without a
+    /// path the file is optional: settings it doesn't provide fall back to `Default`,
+    /// so a deployment can be configured from the environment alone.
+    ///
+    /// Nothing is logged here, since logging itself is configured by the result; the
+    /// returned [`LoadReport`] is logged once it is set up.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<(Self, LoadReport)> {
+        let file = match path {
+            Some(path) => {
+                if !path.is_file() {
+                    anyhow::bail!("Configuration file {} does not exist", path.display());
+                }
+                config::File::from(path)
+            }
+            None => config::File::with_name("config").required(false),
+        };
+        let known = serde_json::to_value(Configuration::default())?;
+        let (environment, from_environment, unknown_variables) = environment_overrides(&known, std::env::vars());
+
+        let from_file = config::Config::builder().add_source(file.clone()).build()?.try_deserialize::<serde_json::Value>()?;
+        let from_file = setting_paths(&from_file);
+        let mut settings = setting_paths(&known);
+        settings.extend(from_file.iter().cloned());
+        settings.extend(from_environment.keys().cloned());
+        let sources = settings
+            .into_iter()
+            .map(|setting| {
+                let source = if let Some(variable) = from_environment.get(&setting) {
+                    variable.clone()
+                } else if from_file.contains(&setting) {
+                    "file".to_string()
+                } else {
+                    "default".to_string()
+                };
+                (setting, source)
+            })
+            .collect();
+
         let settings = config::Config::builder()
-            .add_source(config::File::with_name("config"))
-            .add_source(config::Environment::with_prefix("RECOGNIZR"))
+            .add_source(config::Config::try_from(&Configuration::default())?)
+            .add_source(file)
+            .add_source(config::File::from_str(&environment.to_string(), config::FileFormat::Json))
             .build()?;
 
-        let config = settings.try_deserialize()?;
-        Ok(config)
+        let mut config: Configuration = settings.try_deserialize()?;
+        let mut warnings: Vec<String> = unknown_variables
+            .into_iter()
+            .map(|variable| format!("Ignoring {}: there is no such setting", variable))
+            .collect();
+        warnings.extend(config.models.migrate_flat_paths().into_iter().map(String::from));
+        Ok((config, LoadReport { file_found: !from_file.is_empty(), sources, warnings }))
     }
 
-    pub fn database_url(&self) -> String {
-        format!("{}:{}", self.database.host, self.database.port)
+    /// Validate the semantics of the loaded configuration so bad values fail fast at
+    /// startup instead of mid-inference. Errors name the offending field.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // --- Files ---
+        ensure_file_exists("font.path", &self.font.path)?;
+        ensure_file_exists("models.detector.path", &self.models.detector.path)?;
+        ensure_file_exists("models.recognizer.path", &self.models.recognizer.path)?;
+        if let Some(tls) = &self.tls {
+            ensure_file_exists("tls.cert_path", &tls.cert_path)?;
+            ensure_file_exists("tls.key_path", &tls.key_path)?;
+        }
+
+        // --- Detector ---
+        let detector = &self.models.detector;
+        if detector.strides.is_empty() {
+            anyhow::bail!("models.detector.strides must contain at least one stride");
+        }
+        if let Some(stride) = detector.strides.iter().find(|&&s| s <= 0) {
+            anyhow::bail!("models.detector.strides must be positive, got {}", stride);
+        }
+        if detector.num_anchors == 0 {
+            anyhow::bail!("models.detector.num_anchors must be at least 1");
+        }
+        if !(detector.default_threshold > 0.0 && detector.default_threshold < 1.0) {
+            anyhow::bail!("models.detector.default_threshold must be in (0, 1), got {}", detector.default_threshold);
+        }
+        detector.parsed_stride_thresholds()?;
+        detector.parsed_output_mapping()?;
+        let [height, width] = detector.input_shape;
+        if height == 0 || width == 0 {
+            anyhow::bail!("models.detector.input_shape must be non-zero, got [{}, {}]", height, width);
+        }
+        // decode_proposals derives the feature-map size from input_shape / stride,
+        // which only lines up with the model outputs when the division is exact.
+        let max_stride = *detector.strides.iter().max().unwrap() as u32;
+        if height % max_stride != 0 || width % max_stride != 0 {
+            tracing::warn!(
+                "models.detector.input_shape [{}, {}] is not a multiple of the largest stride {}; detections may be misaligned",
+                height, width, max_stride
+            );
+        }
+        if let Some(max) = detector.max_working_dimension {
+            if max == 0 {
+                anyhow::bail!("models.detector.max_working_dimension must be positive");
+            }
+            if max < height.max(width) {
+                tracing::warn!(
+                    "models.detector.max_working_dimension {} is smaller than the detector input [{}, {}]; small faces may be missed",
+                    max, height, width
+                );
+            }
+        }
+        for &scale in &detector.pyramid_scales {
+            if !(scale > 1.0 && scale <= 8.0) {
+                anyhow::bail!("models.detector.pyramid_scales must be in (1, 8], got {}", scale);
+            }
+        }
+        if detector.device_id < 0 {
+            anyhow::bail!("models.detector.device_id must not be negative, got {}", detector.device_id);
+        }
+
+        // --- Recognizer ---
+        if self.models.recognizer.input_size == 0 {
+            anyhow::bail!("models.recognizer.input_size must be non-zero");
+        }
+        if !(0.0..=1.0).contains(&self.models.recognizer.crop_padding) {
+            anyhow::bail!("models.recognizer.crop_padding must be between 0 and 1, got {}", self.models.recognizer.crop_padding);
+        }
+        if let Some(sharpen) = &self.models.recognizer.sharpen {
+            if !(sharpen.sigma > 0.0 && sharpen.sigma <= 10.0) {
+                anyhow::bail!("models.recognizer.sharpen.sigma must be in (0, 10], got {}", sharpen.sigma);
+            }
+            if !(0..=255).contains(&sharpen.threshold) {
+                anyhow::bail!("models.recognizer.sharpen.threshold must be between 0 and 255, got {}", sharpen.threshold);
+            }
+        }
+        if self.models.recognizer.device_id < 0 {
+            anyhow::bail!("models.recognizer.device_id must not be negative, got {}", self.models.recognizer.device_id);
+        }
+
+        // --- Session ---
+        for (field, options) in [
+            ("models.detector.session", &self.models.detector.session),
+            ("models.recognizer.session", &self.models.recognizer.session),
+        ] {
+            if options.intra_threads == Some(0) || options.inter_threads == Some(0) {
+                anyhow::bail!("{}.intra_threads and inter_threads must be at least 1 when set", field);
+            }
+        }
+
+        if self.models.max_concurrent_inferences == Some(0) {
+            anyhow::bail!("models.max_concurrent_inferences must be at least 1 when set");
+        }
+
+        // --- Normalization ---
+        for (field, normalization) in [
+            ("models.detector.normalization.std", &self.models.detector.normalization),
+            ("models.recognizer.normalization.std", &self.models.recognizer.normalization),
+        ] {
+            if normalization.std.iter().any(|&v| v == 0.0 || !v.is_finite()) {
+                anyhow::bail!("{} must be finite and non-zero, got {:?}", field, normalization.std);
+            }
+        }
+
+        // --- Database ---
+        if self.database.connect_max_attempts == 0 {
+            anyhow::bail!("database.connect_max_attempts must be at least 1");
+        }
+        let person_table = &self.database.person_table;
+        if person_table.is_empty() || !person_table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            anyhow::bail!("database.person_table must be a non-empty name of letters, digits and underscores, got {:?}", person_table);
+        }
+
+        // --- Recognition ---
+        let margin = self.recognition.match_margin;
+        if !(0.0..1.0).contains(&margin) {
+            anyhow::bail!("recognition.match_margin must be in [0, 1), got {}", margin);
+        }
+        let cluster_threshold = self.recognition.cluster_distance_threshold;
+        if !(cluster_threshold > 0.0 && cluster_threshold < 2.0) {
+            anyhow::bail!("recognition.cluster_distance_threshold must be in (0, 2), got {}", cluster_threshold);
+        }
+        let match_threshold = self.recognition.match_threshold;
+        if !(-1.0..=1.0).contains(&match_threshold) {
+            anyhow::bail!("recognition.match_threshold must be in [-1, 1], got {}", match_threshold);
+        }
+        let duplicate_threshold = self.recognition.duplicate_threshold;
+        if !(duplicate_threshold > 0.0 && duplicate_threshold <= 1.0) {
+            anyhow::bail!("recognition.duplicate_threshold must be in (0, 1], got {}", duplicate_threshold);
+        }
+        let min_score = self.recognition.recognize_min_score;
+        if !(0.0..1.0).contains(&min_score) {
+            anyhow::bail!("recognition.recognize_min_score must be in [0, 1), got {}", min_score);
+        }
+        if self.recognition.unknown_label.trim().is_empty() {
+            anyhow::bail!("recognition.unknown_label cannot be empty");
+        }
+        if let Some(group) = self.recognition.allowed_groups.iter().find(|group| !is_valid_group(group)) {
+            anyhow::bail!("recognition.allowed_groups contains an invalid group name: {:?}", group);
+        }
+        for (key, degrees) in [
+            ("max_enrollment_yaw", self.recognition.max_enrollment_yaw),
+            ("max_enrollment_roll", self.recognition.max_enrollment_roll),
+        ] {
+            if !(degrees > 0.0 && degrees <= 90.0) {
+                anyhow::bail!("recognition.{} must be in (0, 90] degrees, got {}", key, degrees);
+            }
+        }
+        if let Some(decimals) = self.recognition.similarity_decimals.filter(|&decimals| decimals > 6) {
+            anyhow::bail!("recognition.similarity_decimals must be at most 6, got {}", decimals);
+        }
+
+        // --- Images ---
+        if self.images.allowed_formats.is_empty() {
+            anyhow::bail!("images.allowed_formats must contain at least one format");
+        }
+        if self.images.max_pixels == 0 {
+            anyhow::bail!("images.max_pixels must be non-zero");
+        }
+        if self.images.max_upload_bytes == 0 {
+            anyhow::bail!("images.max_upload_bytes must be non-zero");
+        }
+
+        // --- Network ---
+        if self.database.port == 0 {
+            anyhow::bail!("database.port must be non-zero");
+        }
+        if self.server.port == 0 {
+            anyhow::bail!("server.port must be non-zero");
+        }
+        if self.server.request_timeout_secs == Some(0) {
+            anyhow::bail!("server.request_timeout_secs must be positive; remove it to disable the timeout");
+        }
+
+        // --- Admin ---
+        if self.admin.token.as_deref().is_some_and(|t| t.trim().is_empty()) {
+            anyhow::bail!("admin.token must not be empty; remove it to disable the admin endpoints");
+        }
+
+        // --- Auth ---
+        if self.auth.api_keys.iter().any(|k| k.trim().is_empty()) {
+            anyhow::bail!("auth.api_keys must not contain empty keys");
+        }
+
+        // --- Rate Limiting ---
+        if self.rate_limit.enabled {
+            let rps = self.rate_limit.requests_per_second;
+            if !rps.is_finite() || rps <= 0.0 {
+                anyhow::bail!("rate_limit.requests_per_second must be positive, got {}", rps);
+            }
+            if self.rate_limit.burst == 0 {
+                anyhow::bail!("rate_limit.burst must be at least 1");
+            }
+        }
+
+        // --- Tracking ---
+        if !(self.tracking.iou_threshold > 0.0 && self.tracking.iou_threshold <= 1.0) {
+            anyhow::bail!("tracking.iou_threshold must be in (0, 1], got {}", self.tracking.iou_threshold);
+        }
+        if self.tracking.requery_interval == 0 {
+            anyhow::bail!("tracking.requery_interval must be at least 1");
+        }
+        if self.tracking.window == 0 {
+            anyhow::bail!("tracking.window must be at least 1");
+        }
+
+        // --- Cache ---
+        if self.cache.enabled {
+            if self.cache.capacity == 0 {
+                anyhow::bail!("cache.capacity must be at least 1");
+            }
+            if self.cache.ttl_secs == 0 {
+                anyhow::bail!("cache.ttl_secs must be at least 1");
+            }
+        }
+
+        // --- Audit ---
+        if self.audit.enabled && self.audit.destination == AuditDestination::Database {
+            let table = &self.audit.table;
+            if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                anyhow::bail!("audit.table must be a non-empty name of letters, digits and underscores, got {:?}", table);
+            }
+        }
+
+        // --- CORS ---
+        for origin in &self.cors.allowed_origins {
+            if origin == "*" || axum::http::HeaderValue::from_str(origin).is_err() {
+                anyhow::bail!("cors.allowed_origins contains an invalid origin: {:?}", origin);
+            }
+        }
+
+        Ok(())
     }
 
     pub fn server_address(&self) -> String {
@@ -85,11 +1311,37 @@ impl Default for Configuration {
                     path: PathBuf::from("models/scrfd_10g_bnkps.onnx"),
                     strides: vec![8, 16, 32],
                     input_shape: [640, 640],
+                    num_anchors: default_num_anchors(),
+                    max_working_dimension: None,
+                    pyramid_scales: Vec::new(),
+                    default_threshold: default_detection_threshold(),
+                    stride_thresholds: HashMap::new(),
+                    output_mapping: HashMap::new(),
+                    normalization: NormalizationConfig::default(),
+                    input_layout: InputLayout::default(),
+                    interpolation: Interpolation::default(),
+                    device_id: 0,
+                    session: SessionOptions::default(),
                 },
                 recognizer: RecognizerConfig {
                     path: PathBuf::from("models/arcface_r100.onnx"),
                     input_size: 112,
+                    crop_padding: 0.0,
+                    normalization: NormalizationConfig::default(),
+                    input_layout: InputLayout::default(),
+                    interpolation: Interpolation::default(),
+                    flip_tta: false,
+                    sharpen: None,
+                    normalize_embeddings: default_normalize_embeddings(),
+                    device_id: 0,
+                    session: SessionOptions::default(),
                 },
+                warmup_iterations: default_warmup_iterations(),
+                concurrent_inference: false,
+                max_concurrent_inferences: None,
+                inference_queue_timeout_ms: default_inference_queue_timeout_ms(),
+                detector_path: None,
+                recognizer_path: None,
             },
             database: DatabaseConfig {
                 host: "127.0.0.1".to_string(),
@@ -98,30 +1350,192 @@ impl Default for Configuration {
                 password: "root".to_string(),
                 namespace: "test".to_string(),
                 database: "test".to_string(),
+                person_table: default_person_table(),
+                connect_max_attempts: default_connect_max_attempts(),
+                connect_initial_backoff_ms: default_connect_initial_backoff_ms(),
+                connect_max_backoff_ms: default_connect_max_backoff_ms(),
             },
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
                 port: 3000,
+                request_timeout_secs: None,
             },
+            logging: LoggingConfig::default(),
+            admin: AdminConfig::default(),
+            auth: AuthConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            cors: CorsConfig::default(),
+            recognition: RecognitionConfig::default(),
+            images: ImageConfig::default(),
+            drawing: DrawingConfig::default(),
+            audit: AuditConfig::default(),
+            storage: StorageConfig::default(),
+            tracking: TrackingConfig::default(),
+            cache: RecognitionCacheConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+/// Whether `group` is a usable gallery group: 1-64 letters, digits, underscores or dashes
+pub fn is_valid_group(group: &str) -> bool {
+    !group.is_empty() && group.len() <= 64 && group.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Collect the `RECOGNIZR_<SECTION>_<SETTING>` variables into a nested settings object,
+/// with the variable each setting path came from. Variable names are matched against the
+/// `known` settings since names like `input_shape` contain underscores themselves.
+/// Values are parsed as JSON where possible (numbers, booleans, arrays, tables), except
+/// for settings that are strings by default. Variables matching no setting are returned
+/// last, to be reported once logging is set up.
+fn environment_overrides(
+    known: &serde_json::Value,
+    variables: impl Iterator<Item = (String, String)>,
+) -> (serde_json::Value, HashMap<String, String>, Vec<String>) {
+    let mut overrides = serde_json::Value::Object(serde_json::Map::new());
+    let mut sources = HashMap::new();
+    let mut unknown = Vec::new();
+    for (variable, value) in variables {
+        // RECOGNIZR_CONFIG names the configuration file itself
+        let Some(key) = variable.strip_prefix("RECOGNIZR_").filter(|key| *key != "CONFIG") else {
+            continue;
+        };
+        let Some(path) = resolve_setting(known, &key.to_lowercase()) else {
+            unknown.push(variable);
+            continue;
+        };
+
+        let is_string = path.iter().try_fold(known, |node, key| node.get(key)).is_some_and(serde_json::Value::is_string);
+        let value = if is_string {
+            serde_json::Value::String(value)
+        } else {
+            serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value))
+        };
+
+        let (leaf, sections) = path.split_last().expect("resolved settings have at least one key");
+        let mut node = &mut overrides;
+        for section in sections {
+            node = node
+                .as_object_mut()
+                .expect("sections are objects")
+                .entry(section.clone())
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        }
+        node.as_object_mut().expect("sections are objects").insert(leaf.clone(), value);
+        sources.insert(path.join("."), variable);
+    }
+    (overrides, sources, unknown)
+}
+
+/// Split an underscore-joined variable name such as `models_detector_input_shape` into the
+/// setting path `models.detector.input_shape`, preferring the longest matching key. Keys
+/// below an unset optional section (e.g. `tls_cert_path`) are taken as they are.
+fn resolve_setting(known: &serde_json::Value, key: &str) -> Option<Vec<String>> {
+    let serde_json::Value::Object(fields) = known else {
+        return None;
+    };
+    let mut names: Vec<&String> = fields.keys().collect();
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+    for name in names {
+        if key == name.as_str() {
+            return Some(vec![name.clone()]);
+        }
+        let Some(rest) = key.strip_prefix(name.as_str()).and_then(|rest| rest.strip_prefix('_')) else {
+            continue;
+        };
+        let nested = match &fields[name] {
+            serde_json::Value::Null => Some(vec![rest.to_string()]),
+            value => resolve_setting(value, rest),
+        };
+        if let Some(mut path) = nested {
+            path.insert(0, name.clone());
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// The dotted paths of every setting in `settings`, e.g. "models.detector.path"
+fn setting_paths(settings: &serde_json::Value) -> BTreeSet<String> {
+    let mut paths = BTreeSet::new();
+    if let serde_json::Value::Object(fields) = settings {
+        for (name, value) in fields {
+            match value {
+                serde_json::Value::Object(nested) if !nested.is_empty() => {
+                    paths.extend(setting_paths(value).into_iter().map(|path| format!("{}.{}", name, path)));
+                }
+                _ => {
+                    paths.insert(name.clone());
+                }
+            }
         }
     }
+    paths
+}
+
+/// Fail with a message naming `field` if `path` does not point at an existing file
+fn ensure_file_exists(field: &str, path: &Path) -> anyhow::Result<()> {
+    if !path.is_file() {
+        anyhow::bail!("{} points to a missing file: {:?}", field, path);
+    }
+    Ok(())
 }
 
 /// Metadata extracted from a model
 #[derive(Debug, Clone)]
 pub struct ModelMetadata {
     pub input_name: String,
+    /// Input shape as `(1, 3, H, W)`, whatever `input_layout` the model expects
     pub input_shape: Vec<i64>,
+    pub input_layout: InputLayout,
     pub output_names: Vec<String>,
+    pub normalization: NormalizationConfig,
+    /// Length of the first output, probed at startup. This is the embedding
+    /// dimension for the recognizer; it is not probed (0) for the detector.
+    pub embedding_dim: usize,
+    /// Whether the model's batch dimension is dynamic, so several inputs can be
+    /// stacked into one run. Only determined for the recognizer.
+    pub dynamic_batch: bool,
+    /// Margin added around face crops (recognizer only, 0 for the detector)
+    pub crop_padding: f32,
+    /// Filter used to resize images to the model input
+    pub interpolation: Interpolation,
+    /// Combine each face's embedding with that of its mirror image (recognizer only)
+    pub flip_tta: bool,
+    /// Unsharp mask for enlarged face crops (recognizer only)
+    pub sharpen: Option<SharpenConfig>,
+    /// Whether embeddings are L2-normalized (recognizer only)
+    pub normalize_embeddings: bool,
 }
 
+/// Detector output indices per stride: stride -> (score_idx, bbox_idx, kps_idx).
+/// `kps_idx` is `None` for detectors exported without keypoint outputs.
+pub type StrideOutputMapping = HashMap<i32, (usize, usize, Option<usize>)>;
+
 #[derive(Debug, Clone)]
 pub struct DetectorMetadata {
     pub input_name: String,
+    /// Input shape as `(1, 3, H, W)`, whatever `input_layout` the model expects
     pub input_shape: Vec<i64>,
+    pub input_layout: InputLayout,
     pub output_names: Vec<String>,
-    /// Pre-computed output mapping: stride -> (score_idx, bbox_idx, kps_idx)
-    pub stride_output_mapping: std::collections::HashMap<i32, (usize, usize, usize)>,
+    pub normalization: NormalizationConfig,
+    pub interpolation: Interpolation,
+    /// Pre-computed output mapping
+    pub stride_output_mapping: StrideOutputMapping,
+    /// Whether every stride maps to the same outputs, which concatenate all strides'
+    /// rows in ascending stride order instead of one set of outputs per stride
+    pub combined_outputs: bool,
+    /// Longest side images are downsized to before detection
+    pub max_working_dimension: Option<u32>,
+    /// Enlargement factors of the extra, tiled detection passes
+    pub pyramid_scales: Vec<f32>,
+    /// Confidence threshold for strides without an override
+    pub default_threshold: f32,
+    /// Confidence threshold overrides per stride
+    pub stride_thresholds: HashMap<i32, f32>,
+    /// Anchors per feature-map location
+    pub num_anchors: usize,
 }
 
 /// Extract basic metadata from a detector model session
@@ -131,6 +1545,7 @@ pub fn extract_detector_metadata(session: &Session, config: &DetectorConfig) ->
         .ok_or_else(|| AppError::BadRequest("Model has no inputs".to_string()))?;
 
     let input_name = input.name.clone();
+    config.input_layout.check_declared("detector", input.input_type.tensor_dimensions())?;
 
     // Use configured input shape (model metadata extraction can be unreliable)
     let input_shape = vec![1, 3, config.input_shape[0] as i64, config.input_shape[1] as i64];
@@ -146,15 +1561,25 @@ pub fn extract_detector_metadata(session: &Session, config: &DetectorConfig) ->
     Ok(ModelMetadata {
         input_name,
         input_shape,
+        input_layout: config.input_layout,
         output_names,
+        normalization: config.normalization.clone(),
+        embedding_dim: 0,
+        dynamic_batch: false,
+        crop_padding: 0.0,
+        interpolation: config.interpolation,
+        flip_tta: false,
+        sharpen: None,
+        normalize_embeddings: false,
     })
 }
 
 /// Create detector metadata with pre-computed output mappings
 pub fn create_detector_metadata_with_mappings(
     basic_metadata: ModelMetadata,
-    stride_output_mapping: std::collections::HashMap<i32, (usize, usize, usize)>,
-) -> DetectorMetadata {
+    stride_output_mapping: StrideOutputMapping,
+    config: &DetectorConfig,
+) -> anyhow::Result<DetectorMetadata> {
     tracing::debug!("Detector model metadata:");
     tracing::debug!("  Input: {} {:?}", basic_metadata.input_name, basic_metadata.input_shape);
     tracing::debug!("  Outputs: {} total", basic_metadata.output_names.len());
@@ -163,21 +1588,38 @@ pub fn create_detector_metadata_with_mappings(
     }
     tracing::debug!("  Pre-computed mappings for {} strides", stride_output_mapping.len());
 
-    DetectorMetadata {
+    let score_outputs: BTreeSet<usize> = stride_output_mapping.values().map(|&(score_idx, _, _)| score_idx).collect();
+    let combined_outputs = stride_output_mapping.len() > 1 && score_outputs.len() == 1;
+
+    Ok(DetectorMetadata {
         input_name: basic_metadata.input_name,
         input_shape: basic_metadata.input_shape,
+        input_layout: basic_metadata.input_layout,
         output_names: basic_metadata.output_names,
+        normalization: basic_metadata.normalization,
+        interpolation: basic_metadata.interpolation,
         stride_output_mapping,
-    }
+        combined_outputs,
+        max_working_dimension: config.max_working_dimension,
+        pyramid_scales: config.pyramid_scales.clone(),
+        default_threshold: config.default_threshold,
+        stride_thresholds: config.parsed_stride_thresholds()?,
+        num_anchors: config.num_anchors,
+    })
 }
 
-/// Extract metadata from a recognizer model session with configured input size
+/// Extract metadata from a recognizer model session with configured input size.
+/// Runs one dummy inference to record the embedding dimension the model produces.
 pub fn extract_recognizer_metadata(session: &Session, config: &RecognizerConfig) -> Result<ModelMetadata, AppError> {
     // Extract input information
     let input = session.inputs.first()
         .ok_or_else(|| AppError::BadRequest("Model has no inputs".to_string()))?;
 
     let input_name = input.name.clone();
+    let dynamic_batch = input.input_type.tensor_dimensions()
+        .and_then(|dims| dims.first())
+        .is_some_and(|&batch| batch < 0);
+    config.input_layout.check_declared("recognizer", input.input_type.tensor_dimensions())?;
 
     // Use configured input size (model metadata extraction can be unreliable)
     let input_shape = vec![1, 3, config.input_size as i64, config.input_size as i64];
@@ -190,9 +1632,33 @@ pub fn extract_recognizer_metadata(session: &Session, config: &RecognizerConfig)
         output_names.push(output.name.clone());
     }
 
+    let output_name = output_names.first()
+        .ok_or_else(|| AppError::BadRequest("Model has no outputs".to_string()))?;
+
+    // Probe the embedding dimension instead of assuming 512
+    let dummy_input = config
+        .input_layout
+        .arrange(Array4::<f32>::zeros((1, 3, config.input_size as usize, config.input_size as usize)));
+    let outputs = session.run(ort::inputs![&input_name => Value::from_array(dummy_input)?]?)?;
+    let embedding_dim = outputs[output_name.as_str()].try_extract_tensor::<f32>()?.len();
+    drop(outputs);
+    tracing::info!("Recognizer produces {}-dimensional embeddings", embedding_dim);
+    if dynamic_batch {
+        tracing::info!("Recognizer has a dynamic batch dimension, faces will be embedded in batches");
+    }
+
     Ok(ModelMetadata {
         input_name,
         input_shape,
+        input_layout: config.input_layout,
         output_names,
+        normalization: config.normalization.clone(),
+        embedding_dim,
+        dynamic_batch,
+        crop_padding: config.crop_padding,
+        interpolation: config.interpolation,
+        flip_tta: config.flip_tta,
+        sharpen: config.sharpen,
+        normalize_embeddings: config.normalize_embeddings,
     })
 }
@@ -0,0 +1,134 @@
+use crate::config::DatabaseConfig;
+use crate::error::AppError;
+use std::future::Future;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    RwLock,
+};
+use std::time::Duration;
+use surrealdb::{
+    engine::remote::ws::{Client, Ws},
+    opt::auth::Root,
+    Surreal,
+};
+
+/// Reconnection attempts made at runtime before a query is reported as unavailable
+const RECONNECT_ATTEMPTS: u32 = 3;
+
+/// SurrealDB handle that transparently re-establishes a dropped websocket connection.
+///
+/// Queries go through [`Database::run`], which on a connection error reconnects
+/// (signin and namespace/database selection included) and retries the query once.
+pub struct Database {
+    config: DatabaseConfig,
+    client: RwLock<Surreal<Client>>,
+    /// Bumped on every successful reconnect so concurrent failures reconnect only once
+    generation: AtomicU64,
+    reconnect_lock: tokio::sync::Mutex<()>,
+}
+
+impl Database {
+    /// Connect at startup, retrying with exponential backoff so the app survives
+    /// starting before SurrealDB is reachable.
+    pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        let client = connect_with_backoff(config, config.connect_max_attempts)
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not connect to SurrealDB at {}: {}", config.url(), e))?;
+
+        Ok(Self {
+            config: config.clone(),
+            client: RwLock::new(client),
+            generation: AtomicU64::new(0),
+            reconnect_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Name of the table holding the enrolled persons
+    pub fn person_table(&self) -> &str {
+        &self.config.person_table
+    }
+
+    /// Run a query against the current connection. If it fails because the connection
+    /// dropped, reconnect and retry once; persistent failures become `DatabaseUnavailable`.
+    pub async fn run<T, F, Fut>(&self, op: F) -> Result<T, AppError>
+    where
+        F: Fn(Surreal<Client>) -> Fut,
+        Fut: Future<Output = surrealdb::Result<T>>,
+    {
+        let generation = self.generation.load(Ordering::Acquire);
+        let client = self.client.read().unwrap().clone();
+
+        match op(client).await {
+            Err(e) if is_connection_error(&e) => {
+                tracing::warn!("Database connection lost: {}", e);
+                self.reconnect(generation).await?;
+                let client = self.client.read().unwrap().clone();
+                op(client).await.map_err(|e| {
+                    if is_connection_error(&e) {
+                        AppError::DatabaseUnavailable(e.to_string())
+                    } else {
+                        AppError::DatabaseError(e)
+                    }
+                })
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Replace the client with a fresh connection, unless another task already did so
+    /// since `seen_generation` was read
+    async fn reconnect(&self, seen_generation: u64) -> Result<(), AppError> {
+        let _guard = self.reconnect_lock.lock().await;
+        if self.generation.load(Ordering::Acquire) != seen_generation {
+            return Ok(());
+        }
+
+        let client = connect_with_backoff(&self.config, RECONNECT_ATTEMPTS)
+            .await
+            .map_err(|e| AppError::DatabaseUnavailable(format!("reconnection failed: {}", e)))?;
+        *self.client.write().unwrap() = client;
+        self.generation.fetch_add(1, Ordering::Release);
+        tracing::info!("Database connection re-established.");
+        Ok(())
+    }
+}
+
+/// Errors that mean the websocket is gone rather than that the query itself failed
+fn is_connection_error(error: &surrealdb::Error) -> bool {
+    use surrealdb::error::Api;
+    matches!(
+        error,
+        surrealdb::Error::Api(Api::Ws(_) | Api::ConnectionUninitialised)
+    )
+}
+
+async fn connect_with_backoff(config: &DatabaseConfig, max_attempts: u32) -> surrealdb::Result<Surreal<Client>> {
+    let max_backoff = Duration::from_millis(config.connect_max_backoff_ms);
+    let mut backoff = Duration::from_millis(config.connect_initial_backoff_ms).min(max_backoff);
+
+    let mut attempt = 1;
+    loop {
+        tracing::info!("Connecting to SurrealDB at {} (attempt {}/{})", config.url(), attempt, max_attempts);
+        match try_connect(config).await {
+            Ok(db) => return Ok(db),
+            Err(e) if attempt < max_attempts => {
+                tracing::warn!("Database connection failed: {}; retrying in {} ms", e, backoff.as_millis());
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+async fn try_connect(config: &DatabaseConfig) -> surrealdb::Result<Surreal<Client>> {
+    let db = Surreal::new::<Ws>(config.url()).await?;
+    db.signin(Root {
+        username: &config.username,
+        password: &config.password,
+    })
+    .await?;
+    db.use_ns(&config.namespace).use_db(&config.database).await?;
+    Ok(db)
+}
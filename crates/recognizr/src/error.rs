@@ -19,22 +19,132 @@ pub enum AppError {
     #[error("Database query failed: {0}")]
     DatabaseError(#[from] surrealdb::Error),
 
+    #[error("SQLite query failed: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error("Database unavailable: {0}")]
+    DatabaseUnavailable(String),
+
     #[error("An internal error occurred: {0}")]
     Internal(#[from] anyhow::Error),
 
     #[error("Invalid request: {0}")]
     BadRequest(String),
+
+    #[error("Invalid image: {0}")]
+    InvalidImage(String),
+
+    #[error("Image too large: {0}")]
+    ImageTooLarge(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("No face detected in the image")]
+    NoFaceDetected,
+
+    #[error("Expected exactly 1 face, but {0} were found")]
+    MultipleFacesDetected(usize),
+
+    #[error("Face is turned away from the camera (yaw {yaw:.0}°, roll {roll:.0}°); face the camera and hold your head upright")]
+    NonFrontalFace { yaw: f32, pitch: f32, roll: f32 },
+
+    #[error("Face matches already enrolled person {name:?} (similarity {similarity:.3}); use ?force=true to enroll anyway")]
+    DuplicatePerson { name: String, similarity: f32 },
+
+    #[error("Embedding dimension mismatch: {0}")]
+    EmbeddingDimensionMismatch(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+
+    #[error("Server overloaded: {0}")]
+    Overloaded(String),
+}
+
+impl AppError {
+    /// Machine-readable error code returned alongside the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::MissingMultipartField(_) => "MISSING_FIELD",
+            AppError::ImageReadError(_) => "IMAGE_ERROR",
+            AppError::InferenceError(_) | AppError::ShapeError(_) => "INFERENCE_ERROR",
+            AppError::DatabaseError(_) | AppError::SqliteError(_) => "DB_ERROR",
+            AppError::DatabaseUnavailable(_) => "DB_UNAVAILABLE",
+            AppError::Internal(_) => "INTERNAL_ERROR",
+            AppError::BadRequest(_) => "BAD_REQUEST",
+            AppError::InvalidImage(_) => "INVALID_IMAGE",
+            AppError::ImageTooLarge(_) => "IMAGE_TOO_LARGE",
+            AppError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::NoFaceDetected => "NO_FACE",
+            AppError::MultipleFacesDetected(_) => "MULTIPLE_FACES",
+            AppError::NonFrontalFace { .. } => "NON_FRONTAL_FACE",
+            AppError::DuplicatePerson { .. } => "DUPLICATE_PERSON",
+            AppError::EmbeddingDimensionMismatch(_) => "EMBEDDING_DIM_MISMATCH",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::TooManyRequests(_) => "RATE_LIMITED",
+            AppError::Timeout(_) => "TIMEOUT",
+            AppError::Overloaded(_) => "OVERLOADED",
+        }
+    }
+
+    /// Additional structured fields merged into the JSON error body
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            AppError::NoFaceDetected => Some(json!({ "faces": 0 })),
+            AppError::MultipleFacesDetected(count) => Some(json!({ "faces": count })),
+            AppError::NonFrontalFace { yaw, pitch, roll } => Some(json!({ "yaw": yaw, "pitch": pitch, "roll": roll })),
+            AppError::DuplicatePerson { name, similarity } => {
+                Some(json!({ "existing_name": name, "similarity": similarity }))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let code = self.code();
+        let details = self.details();
         let (status, error_message) = match self {
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::InvalidImage(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::ImageTooLarge(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg),
             AppError::MissingMultipartField(field) => (StatusCode::BAD_REQUEST, format!("Missing field: {}", field)),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::EmbeddingDimensionMismatch(msg) => (StatusCode::CONFLICT, msg),
+            e @ AppError::DuplicatePerson { .. } => (StatusCode::CONFLICT, e.to_string()),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            AppError::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            AppError::DatabaseUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            AppError::Timeout(msg) => (StatusCode::GATEWAY_TIMEOUT, msg),
+            AppError::Overloaded(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            e @ (AppError::NoFaceDetected | AppError::MultipleFacesDetected(_) | AppError::NonFrontalFace { .. }) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+            }
             e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
         };
 
-        let body = Json(json!({ "error": error_message }));
-        (status, body).into_response()
+        let mut body = json!({ "error": error_message, "code": code });
+        if let (Some(body), Some(serde_json::Value::Object(details))) = (body.as_object_mut(), details) {
+            body.extend(details);
+        }
+        (status, Json(body)).into_response()
     }
 }
\ No newline at end of file
@@ -1,42 +1,150 @@
+use crate::audit::now_millis;
+use crate::auth::{require_admin, require_api_key};
+use crate::cache::CacheKey;
+use crate::config::{is_valid_group, CorsConfig, ImageConfig, RecognitionConfig};
 use crate::error::AppError;
-use crate::models::{DebugParams, DetectedFace, FinalResult, GalleryPerson, Person, RecognitionResult};
-use crate::pipeline::{detect_faces, draw_detections, get_recognition_embedding, create_gallery_crop, X_OFFSET, Y_OFFSET};
+use crate::models::{
+    AnnotatedRecognition, ClusterResponse, ClusteredFace, DebugParams, DetectedFace, Detection, EnrollParams, ExportParams, ExportedPerson, FinalResult, GalleryParams, GalleryPerson, GalleryStatus, ImageUploadBody, ImportFailure,
+    ImportReport, ModelStats, Person, PersonDetails, PersonSummary, RecognitionResult, ReembedFailure, ReembedReport, RenamePerson, SimilarPair, SimilarityMatrix,
+    SimilarityParams, Stats, StoredEmbedding,
+};
+use crate::pipeline::{
+    cluster_embeddings, decode_image, detect_faces, estimate_pose, draw_anchor_grid, draw_detections, draw_keypoint_labels, get_recognition_embedding, get_recognition_embeddings, create_gallery_crop, l2_norm,
+    content_hash, perceptual_hash, prepare_recognition_input, run_recognizer, similar_pairs, tensor_to_image, TrackedFace, X_OFFSET, Y_OFFSET,
+};
+use crate::negotiate::negotiate_errors;
+use crate::rate_limit::rate_limit;
+use crate::timeout::request_timeout;
 use crate::AppState;
 use axum::routing::{get, post};
 use axum::{
-    extract::{DefaultBodyLimit, Multipart, Query, State},
-    http::{header, HeaderMap, StatusCode},
+    body::Body,
+    extract::{multipart::{Field, MultipartError}, DefaultBodyLimit, FromRequest, Multipart, Path, Query, Request, State},
+    middleware,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use image::{DynamicImage, GenericImageView};
-use tower_http::cors::{CorsLayer, Any};
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
+use futures_util::{stream, StreamExt};
 use tracing::debug;
-use std::sync::Arc;
-use std::time::Instant;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::any::Any as PanicPayload;
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedSemaphorePermit;
 use base64::{Engine as _, engine::general_purpose};
 
 // --- VALIDATION CONSTANTS ---
-const MAX_IMAGE_SIZE: usize = 15 * 1024 * 1024; // 15MB
+const MAX_CLUSTER_IMAGES: usize = 20;
+/// Allowance for multipart boundaries and the non-image fields on top of the image itself
+const MULTIPART_OVERHEAD_BYTES: usize = 64 * 1024;
 const MAX_NAME_LENGTH: usize = 100;
+/// Upper bound for the text fields of a multipart form (`name`, `bbox`)
+const MAX_TEXT_FIELD_BYTES: usize = 1024;
+/// Enrolled faces less frontal than this make poor reference embeddings and are logged
+const MIN_ENROLL_FRONTALNESS: f32 = 0.8;
+/// Most pairs returned by one page of /admin/similarity-matrix
+const MAX_SIMILARITY_PAGE: usize = 1000;
+/// Bound for `offset + limit` of /admin/similarity-matrix, the pairs held while ranking
+const MAX_SIMILARITY_PAIRS: usize = 100_000;
+/// Upper bound for a gallery export uploaded to /admin/import
+const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+/// Upper bound for one line of an import, the most of it buffered at a time
+const MAX_IMPORT_LINE_BYTES: usize = 16 * 1024 * 1024;
+/// Persons read from the store at a time by /admin/export
+const EXPORT_PAGE_SIZE: usize = 100;
 const MIN_IMAGE_DIMENSION: u32 = 32;
 const MAX_IMAGE_DIMENSION: u32 = 8192;
 
-pub fn create_router() -> axum::Router<Arc<AppState>> {
-    // Configure CORS to allow requests from the frontend
-    let cors = CorsLayer::new()
-        .allow_origin(Any) // In production, specify exact origins like "http://localhost:5173"
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    axum::Router::new()
-        .route("/health", get(health_handler))
+pub fn create_router(state: Arc<AppState>) -> axum::Router {
+    let cors = build_cors_layer(&state.config.cors);
+
+    // Admin endpoints are guarded by the configured admin token
+    let admin = axum::Router::new()
+        .route("/reload", post(reload_handler))
+        .route("/reembed", post(reembed_handler))
+        .route("/export", get(export_handler))
+        .route("/similarity-matrix", get(similarity_matrix_handler))
+        .route("/stats", get(stats_handler))
+        .route("/import", post(import_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin));
+
+    // Everything except the health check requires an API key when auth is enabled
+    let api = axum::Router::new()
         .route("/enroll", post(enroll_handler))
         .route("/enroll-from-bbox", post(enroll_from_bbox_handler))
         .route("/recognize", post(recognize_handler))
+        .route("/detect", post(detect_handler))
+        .route(
+            "/cluster",
+            post(cluster_handler).layer(DefaultBodyLimit::max(
+                MAX_CLUSTER_IMAGES * state.config.images.max_upload_bytes + MULTIPART_OVERHEAD_BYTES,
+            )),
+        )
         .route("/gallery", get(gallery_handler))
+        .route("/persons/{id}", get(get_person_handler).patch(rename_person_handler).delete(delete_person_handler))
         .route("/debug/detector", axum::routing::post(debug_detector_handler))
-        .layer(DefaultBodyLimit::max(15 * 1024 * 1024)) // 15MB limit for image uploads
+        .route("/debug/recognizer", post(debug_recognizer_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), request_timeout))
+        .route_layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key));
+
+    axum::Router::new()
+        .route("/health", get(health_handler))
+        .merge(api)
+        .nest("/admin", admin)
+        .layer(DefaultBodyLimit::max(upload_body_limit(state.config.images.max_upload_bytes)))
+        .layer(CatchPanicLayer::custom(panic_response))
+        .layer(middleware::from_fn_with_state(state.clone(), negotiate_errors))
         .layer(cors) // Add CORS layer
+        .with_state(state)
+}
+
+/// Request body limit for single-image uploads. JSON uploads carry the image base64
+/// encoded, a third larger than the raw bytes; the decoded image is checked against
+/// `max_upload_bytes` either way.
+fn upload_body_limit(max_upload_bytes: usize) -> usize {
+    max_upload_bytes.div_ceil(3) * 4 + MULTIPART_OVERHEAD_BYTES
+}
+
+/// Build the CORS layer from the configured origin allowlist, falling back to a
+/// permissive policy when no origins are configured (development mode)
+fn build_cors_layer(config: &CorsConfig) -> CorsLayer {
+    if config.allowed_origins.is_empty() {
+        tracing::warn!("No CORS origins configured, allowing requests from any origin");
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any);
+    }
+
+    // Origins were validated at startup
+    let origins: Vec<HeaderValue> = config.allowed_origins
+        .iter()
+        .filter_map(|origin| origin.parse().ok())
+        .collect();
+
+    // Wildcards are not allowed together with credentials, so methods and headers are explicit
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+}
+
+/// Turn a handler panic into a logged 500 response instead of dropping the connection
+fn panic_response(panic: Box<dyn PanicPayload + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic payload");
+    tracing::error!("Request handler panicked: {}", message);
+    AppError::Internal(anyhow::anyhow!("The request could not be completed")).into_response()
 }
 
 // Simple health check endpoint that doesn't require database access
@@ -49,17 +157,23 @@ async fn health_handler() -> Json<serde_json::Value> {
 }
 
 // Gallery endpoint to get all enrolled people with their cropped images
-async fn gallery_handler(State(state): State<Arc<AppState>>) -> Result<Json<Vec<GalleryPerson>>, AppError> {
-    let people: Vec<Person> = state.db
-        .select("person")
-        .await?;
+async fn gallery_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GalleryParams>,
+) -> Result<Json<Vec<GalleryPerson>>, AppError> {
+    validate_group(&state, params.group.as_deref())?;
+    let people = state.store.list().await?;
 
     // Convert to gallery format with base64 encoded images
     let gallery_people: Vec<GalleryPerson> = people
         .into_iter()
+        .filter(|person| params.group.is_none() || person.group == params.group)
         .map(|person| GalleryPerson {
             name: person.name,
             image_base64: general_purpose::STANDARD.encode(&person.cropped_image),
+            group: person.group,
+            created_at: person.created_at,
+            updated_at: person.updated_at,
         })
         .collect();
 
@@ -70,104 +184,602 @@ async fn gallery_handler(State(state): State<Arc<AppState>>) -> Result<Json<Vec<
     Ok(Json(sorted_gallery))
 }
 
-async fn enroll_handler(
+// Metadata of one enrolled person, without the embedding itself. Accepts the record id
+// with or without the "person:" table prefix.
+async fn get_person_handler(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<DebugParams>,
-    multipart: Multipart,
+    Path(id): Path<String>,
+) -> Result<Json<PersonDetails>, AppError> {
+    let person = state.store
+        .get(&id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Person {} does not exist", id)))?;
+    Ok(Json(person))
+}
+
+// Correct the name of an enrolled person. Accepts the record id with or without the
+// "person:" table prefix.
+async fn rename_person_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(body): Json<RenamePerson>,
+) -> Result<Json<PersonSummary>, AppError> {
+    validate_name(&body.name)?;
+    let updated = state.store
+        .rename(&id, &body.name)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Person {} does not exist", id)))?;
+    gallery_changed(&state);
+    debug!("Renamed {} to {:?}", updated.id, updated.name);
+    Ok(Json(updated))
+}
+
+// Remove an enrolled person. Accepts the record id with or without the "person:" prefix.
+async fn delete_person_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
 ) -> Result<StatusCode, AppError> {
-    let (name, image_bytes) = parse_enroll_multipart(multipart).await?;
+    if !state.store.delete(&id).await? {
+        return Err(AppError::NotFound(format!("Person {} does not exist", id)));
+    }
+    // The count is this instance's view; persons enrolled elsewhere may be deleted here,
+    // so it must not wrap below zero
+    let _ = state.gallery_size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| Some(size.saturating_sub(1)));
+    gallery_changed(&state);
+    debug!("Deleted person {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Insert a newly enrolled person and return the created record's id and name. The
+/// timestamps are set to now unless they are already known (imports keep theirs).
+async fn store_person(state: &AppState, mut person: Person) -> Result<PersonSummary, AppError> {
+    let created_at = *person.created_at.get_or_insert_with(now_millis);
+    person.updated_at.get_or_insert(created_at);
+    let created = state.store.enroll(person).await?;
+    state.gallery_size.fetch_add(1, Ordering::Relaxed);
+    gallery_changed(state);
+    debug!("Enrolled {:?} as {}", created.name, created.id);
+
+    Ok(created)
+}
 
-    // Validate name
+/// Drop cached recognitions, which may name persons that were changed or miss new ones
+fn gallery_changed(state: &AppState) {
+    if let Some(cache) = &state.cache {
+        cache.invalidate();
+    }
+}
+
+/// Decode an uploaded image after checking its size, and check its dimensions. The
+/// format allowlist and the pixel budget are enforced by `decode_image` before any
+/// pixel data is decoded. Every endpoint accepting an upload goes through here.
+fn validate_and_decode_image(image_bytes: &[u8], config: &ImageConfig) -> Result<DynamicImage, AppError> {
+    if image_bytes.is_empty() {
+        return Err(AppError::InvalidImage("Image data is empty".to_string()));
+    }
+    if image_bytes.len() > config.max_upload_bytes {
+        return Err(AppError::PayloadTooLarge(format!("Image too large (max {:.1} MB)", config.max_upload_bytes as f64 / (1024.0 * 1024.0))));
+    }
+
+    let image = decode_image(image_bytes, config)?;
+    let (width, height) = image.dimensions();
+    if width < MIN_IMAGE_DIMENSION || height < MIN_IMAGE_DIMENSION {
+        return Err(AppError::InvalidImage(format!("Image too small (min {}x{})", MIN_IMAGE_DIMENSION, MIN_IMAGE_DIMENSION)));
+    }
+    if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        return Err(AppError::ImageTooLarge(format!("Image too large (max {}x{})", MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION)));
+    }
+    Ok(image)
+}
+
+/// Validate a person's name as given at enrollment or rename
+fn validate_name(name: &str) -> Result<(), AppError> {
     if name.trim().is_empty() {
         return Err(AppError::BadRequest("Name cannot be empty".to_string()));
     }
     if name.len() > MAX_NAME_LENGTH {
         return Err(AppError::BadRequest(format!("Name too long (max {} characters)", MAX_NAME_LENGTH)));
     }
+    Ok(())
+}
 
-    // Validate image size
-    if image_bytes.is_empty() {
-        return Err(AppError::BadRequest("Image data is empty".to_string()));
+/// Check a `?group=` parameter against `recognition.allowed_groups`; no group selects
+/// the default gallery and is always allowed
+fn validate_group(state: &AppState, group: Option<&str>) -> Result<(), AppError> {
+    let Some(group) = group else {
+        return Ok(());
+    };
+    if !is_valid_group(group) {
+        return Err(AppError::BadRequest("Group must be 1-64 letters, digits, underscores or dashes".to_string()));
     }
-    if image_bytes.len() > MAX_IMAGE_SIZE {
-        return Err(AppError::BadRequest(format!("Image too large (max {} MB)", MAX_IMAGE_SIZE / (1024 * 1024))));
+    let allowed = &state.config.recognition.allowed_groups;
+    if !allowed.is_empty() && !allowed.iter().any(|allowed| allowed == group) {
+        return Err(AppError::BadRequest(format!("Unknown group {:?}", group)));
     }
+    Ok(())
+}
 
-    let original_image = image::load_from_memory(&image_bytes)?;
-    let (original_w, original_h) = original_image.dimensions();
+// Summary of the gallery and the loaded models for monitoring. Counting the gallery
+// also refreshes the cached gallery state.
+async fn stats_handler(State(state): State<Arc<AppState>>) -> Result<Json<Stats>, AppError> {
+    let gallery = reload_gallery(&state).await?;
+    let models = &state.config.models;
+    let on_cuda = state.execution_provider == "CUDA";
+
+    Ok(Json(Stats {
+        gallery,
+        embedding_dim: state.recognizer_metadata.embedding_dim,
+        embeddings_normalized: state.recognizer_metadata.normalize_embeddings,
+        execution_provider: state.execution_provider,
+        // CPU-only builds never request CUDA
+        cuda_fallback: cfg!(feature = "cuda") && !on_cuda,
+        detector: ModelStats {
+            path: models.detector.path.display().to_string(),
+            input_shape: state.detector_metadata.input_shape.clone(),
+            device_id: on_cuda.then_some(models.detector.device_id),
+        },
+        recognizer: ModelStats {
+            path: models.recognizer.path.display().to_string(),
+            input_shape: state.recognizer_metadata.input_shape.clone(),
+            device_id: on_cuda.then_some(models.recognizer.device_id),
+        },
+    }))
+}
+
+// Re-read the person table and rebuild the derived gallery state. Lets instances pick up
+// enrollments made directly in SurrealDB or by another instance without a restart.
+async fn reload_handler(State(state): State<Arc<AppState>>) -> Result<Json<GalleryStatus>, AppError> {
+    gallery_changed(&state);
+    Ok(Json(reload_gallery(&state).await?))
+}
+
+/// Refresh the cached gallery size and the number of embeddings that don't match the
+/// recognizer's dimension from the database
+pub async fn reload_gallery(state: &AppState) -> Result<GalleryStatus, AppError> {
+    let status = state.store.status(state.recognizer_metadata.embedding_dim).await?;
+
+    state.gallery_size.store(status.persons, Ordering::Relaxed);
+    state.incompatible_embeddings.store(status.incompatible, Ordering::Relaxed);
+    debug!("Gallery reloaded with {} persons ({} incompatible)", status.persons, status.incompatible);
+    Ok(status)
+}
+
+// Recompute every stored embedding from the person's gallery crop with the current
+// recognizer, so a model upgrade doesn't force everyone to re-enroll. Records that fail
+// are reported individually and keep their old embedding.
+async fn reembed_handler(State(state): State<Arc<AppState>>) -> Result<Json<ReembedReport>, AppError> {
+    let records = state.store.list().await?;
+
+    let total = records.len();
+    tracing::info!("Re-embedding {} persons", total);
+    let mut report = ReembedReport { total, updated: 0, failed: Vec::new() };
+
+    for (index, record) in records.into_iter().enumerate() {
+        let cropped_image = record.cropped_image;
+        let result = async {
+            let slot = inference_permit(&state).await?;
+            let embedding = run_inference(&state, &slot, move |state| embed_stored_crop(state, &cropped_image)).await?;
+            drop(slot);
+            state.store.update_embedding(&record.id, embedding).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => report.updated += 1,
+            Err(e) => {
+                tracing::warn!("Failed to re-embed {} ({}): {}", record.name, record.id, e);
+                report.failed.push(ReembedFailure { id: record.id, name: record.name, error: e.to_string() });
+            }
+        }
+
+        if (index + 1) % 50 == 0 {
+            tracing::info!("Re-embedded {}/{} persons", index + 1, total);
+        }
+    }
+
+    gallery_changed(&state);
+    reload_gallery(&state).await?;
+    tracing::info!("Re-embedding finished: {} updated, {} failed", report.updated, report.failed.len());
+    Ok(Json(report))
+}
+
+// Dump the whole gallery as JSON lines, one person per line with name, embedding and
+// (unless ?crops=false) the base64 gallery crop, for backups and migrating instances
+async fn export_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ExportParams>,
+) -> Result<([(header::HeaderName, HeaderValue); 2], Body), AppError> {
+    // The first page is read up front, so a failing store still answers with an error status
+    let first_page = state.store.export(0, EXPORT_PAGE_SIZE).await?;
+    tracing::info!("Exporting the gallery");
+
+    // Only one page of persons is held at a time; a store error past the first page ends
+    // the response early
+    let pages = stream::unfold(Some((0, Ok(first_page))), move |page| {
+        let state = state.clone();
+        async move {
+            let (offset, persons) = page?;
+            let next = match &persons {
+                Ok(persons) if persons.len() == EXPORT_PAGE_SIZE => {
+                    let offset = offset + persons.len();
+                    Some((offset, state.store.export(offset, EXPORT_PAGE_SIZE).await))
+                }
+                _ => None,
+            };
+            Some((persons, next))
+        }
+    });
+    let lines = pages.map(move |persons| {
+        let mut lines = String::new();
+        for person in persons? {
+            let exported = ExportedPerson {
+                name: person.name,
+                embedding: person.embedding,
+                cropped_image: params.crops.then(|| general_purpose::STANDARD.encode(&person.cropped_image)),
+                group: person.group,
+                created_at: person.created_at,
+                updated_at: person.updated_at,
+            };
+            lines += &serde_json::to_string(&exported).map_err(|e| AppError::Internal(e.into()))?;
+            lines.push('\n');
+        }
+        Ok::<_, AppError>(lines)
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson")),
+            (header::CONTENT_DISPOSITION, HeaderValue::from_static("attachment; filename=\"gallery.jsonl\"")),
+        ],
+        Body::from_stream(lines),
+    ))
+}
 
-    // Validate image dimensions
-    if original_w < MIN_IMAGE_DIMENSION || original_h < MIN_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too small (min {}x{})", MIN_IMAGE_DIMENSION, MIN_IMAGE_DIMENSION)));
+// Pairs of enrolled persons of the same group whose embeddings are at least `threshold`
+// similar (default `recognition.duplicate_threshold`), most similar first, to find persons
+// enrolled twice or under the wrong name. Computed on request over the stored embeddings
+// and paged with `offset` and `limit`.
+async fn similarity_matrix_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SimilarityParams>,
+) -> Result<Json<SimilarityMatrix>, AppError> {
+    let threshold = params.threshold.unwrap_or(state.config.recognition.duplicate_threshold);
+    if !(-1.0..=1.0).contains(&threshold) {
+        return Err(AppError::BadRequest(format!("threshold must be in [-1, 1], got {}", threshold)));
+    }
+    if params.limit == 0 || params.limit > MAX_SIMILARITY_PAGE {
+        return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_SIMILARITY_PAGE)));
     }
-    if original_w > MAX_IMAGE_DIMENSION || original_h > MAX_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too large (max {}x{})", MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION)));
+    let keep = params.offset.saturating_add(params.limit);
+    if keep > MAX_SIMILARITY_PAIRS {
+        return Err(AppError::BadRequest(format!("offset + limit must be at most {}", MAX_SIMILARITY_PAIRS)));
     }
 
-    let (mut faces, new_w, new_h) = {
-        let mut detector_session_guard = state.detector_session.lock().unwrap();
-        detect_faces(&mut detector_session_guard, &image_bytes, &params, &state.detector_metadata)?
+    let persons = state.store.embeddings().await?;
+    tracing::info!("Comparing the embeddings of {} persons", persons.len());
+
+    // Quadratic in the gallery size, so kept off the async runtime
+    let SimilarityParams { offset, limit, .. } = params;
+    let (total, pairs) = tokio::task::spawn_blocking(move || {
+        // Only persons of the same group with embeddings of the same dimension are comparable
+        let mut partitions: BTreeMap<(Option<String>, usize), Vec<StoredEmbedding>> = BTreeMap::new();
+        for person in persons {
+            partitions.entry((person.group.clone(), person.embedding.len())).or_default().push(person);
+        }
+
+        let mut total = 0;
+        let mut pairs = Vec::new();
+        for mut members in partitions.into_values() {
+            let embeddings: Vec<Vec<f32>> = members.iter_mut().map(|member| std::mem::take(&mut member.embedding)).collect();
+            let (count, partition_pairs) = similar_pairs(&embeddings, threshold, keep);
+            total += count;
+            let summary = |member: &StoredEmbedding| PersonSummary { id: member.id.clone(), name: member.name.clone() };
+            pairs.extend(partition_pairs.into_iter().map(|(i, j, similarity)| SimilarPair {
+                a: summary(&members[i]),
+                b: summary(&members[j]),
+                group: members[i].group.clone(),
+                similarity,
+            }));
+        }
+        pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        (total, pairs.into_iter().skip(offset).take(limit).collect())
+    })
+    .await
+    .map_err(|e| AppError::Internal(e.into()))?;
+
+    Ok(Json(SimilarityMatrix { threshold, total, offset, pairs }))
+}
+
+// Enroll every person of an /admin/export dump. Lines that fail to parse, have an invalid
+// name, an embedding of the wrong dimension or an undecodable crop are reported and skipped.
+// Imported persons are added to the gallery; existing persons are left untouched. The body
+// is read line by line, so an upload that turns out too large or breaks off keeps the
+// persons imported before it failed.
+async fn import_handler(State(state): State<Arc<AppState>>, body: Body) -> Result<Json<ImportReport>, AppError> {
+    let mut report = ImportReport { total: 0, imported: 0, failed: Vec::new() };
+    let mut chunks = body.into_data_stream();
+    let mut pending = Vec::new();
+    let mut received = 0;
+    let mut line_number = 0;
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| AppError::BadRequest(format!("Failed to read the import: {}", e)))?;
+        received += chunk.len();
+        if received > MAX_IMPORT_BYTES {
+            return Err(AppError::PayloadTooLarge(format!("Imports are limited to {} bytes", MAX_IMPORT_BYTES)));
+        }
+        pending.extend_from_slice(&chunk);
+
+        for line in take_lines(&mut pending) {
+            line_number += 1;
+            import_line(&state, &mut report, line_number, &line).await;
+        }
+        if pending.len() > MAX_IMPORT_LINE_BYTES {
+            return Err(AppError::PayloadTooLarge(format!(
+                "Line {} is longer than {} bytes",
+                line_number + 1,
+                MAX_IMPORT_LINE_BYTES
+            )));
+        }
+    }
+    // The last line may lack its newline
+    if !pending.is_empty() {
+        import_line(&state, &mut report, line_number + 1, &pending).await;
+    }
+
+    tracing::info!("Import finished: {} imported, {} failed", report.imported, report.failed.len());
+    Ok(Json(report))
+}
+
+/// Split the complete lines off the front of `pending`, leaving any unfinished last line
+fn take_lines(pending: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') else {
+        return Vec::new();
     };
+    let rest = pending.split_off(end + 1);
+    let complete = std::mem::replace(pending, rest);
+    complete[..end].split(|&byte| byte == b'\n').map(<[u8]>::to_vec).collect()
+}
 
-    if faces.len() != 1 {
-        return Err(AppError::BadRequest(format!(
-            "Enrollment requires exactly 1 face, but {} were found.",
-            faces.len()
-        )));
+/// Enroll the person of one import line, recording the outcome in `report`. Blank lines are skipped.
+async fn import_line(state: &AppState, report: &mut ImportReport, line_number: usize, line: &[u8]) {
+    if line.trim_ascii().is_empty() {
+        return;
     }
+    report.total += 1;
+
+    let result = async {
+        let exported: ExportedPerson = serde_json::from_slice(line)
+            .map_err(|e| AppError::BadRequest(format!("Invalid export line: {}", e)))?;
+        validate_name(&exported.name)?;
+        validate_group(state, exported.group.as_deref())?;
+        ensure_embedding_dim(state, &exported.embedding)?;
+        let cropped_image = match exported.cropped_image {
+            Some(encoded) => general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| AppError::BadRequest(format!("Invalid base64 crop: {}", e)))?,
+            None => Vec::new(),
+        };
+        let person = Person {
+            name: exported.name,
+            embedding: exported.embedding,
+            cropped_image,
+            group: exported.group,
+            created_at: exported.created_at,
+            updated_at: exported.updated_at,
+        };
+        store_person(state, person).await
+    }
+    .await;
 
-    let scale_w = original_w as f32 / new_w as f32;
-    let scale_h = original_h as f32 / new_h as f32;
+    match result {
+        Ok(_) => report.imported += 1,
+        Err(e) => report.failed.push(ImportFailure { line: line_number, error: e.to_string() }),
+    }
+}
 
-    let face = &mut faces[0];
-    face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
+/// Detect the face in a stored gallery crop and compute its embedding
+fn embed_stored_crop(state: &AppState, cropped_image: &[u8]) -> Result<Vec<f32>, AppError> {
+    let image = image::load_from_memory(cropped_image)?;
+    let (width, height) = image.dimensions();
 
-    let embedding = {
-        let mut recognizer_session_guard = state.recognizer_session.lock().unwrap();
-        get_recognition_embedding(&mut recognizer_session_guard, &original_image, face, &state.recognizer_metadata)?
+    let (faces, new_w, new_h) = {
+        let detector_session_guard = state.detector_session.acquire();
+        detect_faces(&detector_session_guard, &image, &DebugParams::default(), &state.detector_metadata)?
     };
 
-    // Create gallery crop (256x256 square image)
-    let cropped_image = create_gallery_crop(&original_image, face, 256)?;
+    // The crop is padded around the enrolled face, so the largest detection is that face
+    let mut face = faces
+        .into_iter()
+        .max_by(|a, b| bbox_area(&a.bbox).total_cmp(&bbox_area(&b.bbox)))
+        .ok_or(AppError::NoFaceDetected)?;
+    face.scale_to_original(width as f32 / new_w as f32, height as f32 / new_h as f32, X_OFFSET, Y_OFFSET);
+
+    let recognizer_session_guard = state.recognizer_session.acquire();
+    get_recognition_embedding(&recognizer_session_guard, &image, &face, &state.recognizer_metadata)
+}
 
-    let person = Person { name, embedding, cropped_image };
-    let _created_person: Option<Person> = state.db.create("person").content(person).await?;
+/// A slot of `models.max_concurrent_inferences`, shared by the request that took it and the
+/// inference runs it started. The slot frees up once all of them have let go of it.
+type InferenceSlot = Arc<Option<OwnedSemaphorePermit>>;
 
-    Ok(StatusCode::CREATED)
+/// Wait for an inference slot when `models.max_concurrent_inferences` is set, failing with
+/// 503 if none frees up within `inference_queue_timeout_ms`.
+async fn inference_permit(state: &AppState) -> Result<InferenceSlot, AppError> {
+    let Some(slots) = &state.inference_slots else {
+        return Ok(Arc::new(None));
+    };
+    let wait = Duration::from_millis(state.config.models.inference_queue_timeout_ms);
+    match tokio::time::timeout(wait, Arc::clone(slots).acquire_owned()).await {
+        Ok(permit) => Ok(Arc::new(Some(permit.map_err(|e| AppError::Internal(e.into()))?))),
+        Err(_) => Err(AppError::Overloaded(format!(
+            "No inference slot became free within {} ms, try again later",
+            wait.as_millis()
+        ))),
+    }
 }
 
-async fn enroll_from_bbox_handler(
-    State(state): State<Arc<AppState>>,
-    multipart: Multipart,
-) -> Result<StatusCode, AppError> {
-    let (name, image_bytes, bbox) = parse_enroll_bbox_multipart(multipart).await?;
+/// Run the models on the blocking thread pool, so the runtime keeps serving other requests
+/// and the request timeout can abort the request in the meantime. An aborted run still
+/// completes on its thread, keeping its sessions and `slot` until it returns.
+async fn run_inference<T, F>(state: &Arc<AppState>, slot: &InferenceSlot, inference: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&AppState) -> Result<T, AppError> + Send + 'static,
+{
+    let state = Arc::clone(state);
+    let slot = Arc::clone(slot);
+    tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        inference(&state)
+    })
+    .await
+    .unwrap_or_else(|e| match e.try_into_panic() {
+        // Re-raised so the panic is still answered by the CatchPanicLayer
+        Ok(panic) => std::panic::resume_unwind(panic),
+        Err(e) => Err(AppError::Internal(e.into())),
+    })
+}
 
-    // Validate name
-    if name.trim().is_empty() {
-        return Err(AppError::BadRequest("Name cannot be empty".to_string()));
+/// Reject an embedding whose length doesn't match the recognizer's output dimension
+fn ensure_embedding_dim(state: &AppState, embedding: &[f32]) -> Result<(), AppError> {
+    let expected = state.recognizer_metadata.embedding_dim;
+    if embedding.len() != expected {
+        return Err(AppError::EmbeddingDimensionMismatch(format!(
+            "Embedding has {} dimensions, but the recognizer produces {}",
+            embedding.len(),
+            expected
+        )));
     }
-    if name.len() > MAX_NAME_LENGTH {
-        return Err(AppError::BadRequest(format!("Name too long (max {} characters)", MAX_NAME_LENGTH)));
+    Ok(())
+}
+
+/// Reject an enrollment whose face closely matches an already enrolled person of the same
+/// group, unless forced. Skipped while the gallery holds embeddings of another dimension,
+/// which can't be compared.
+async fn ensure_not_duplicate(state: &AppState, embedding: &[f32], params: &EnrollParams) -> Result<(), AppError> {
+    if params.force || state.incompatible_embeddings.load(Ordering::Relaxed) > 0 {
+        return Ok(());
     }
 
-    // Validate image size
-    if image_bytes.is_empty() {
-        return Err(AppError::BadRequest("Image data is empty".to_string()));
+    let candidates = find_top_matches(state, embedding.to_vec(), params.group.as_deref()).await?;
+    match candidates.into_iter().next() {
+        Some(best) if best.similarity > state.config.recognition.duplicate_threshold => {
+            Err(AppError::DuplicatePerson { name: best.name, similarity: best.similarity })
+        }
+        _ => Ok(()),
     }
-    if image_bytes.len() > MAX_IMAGE_SIZE {
-        return Err(AppError::BadRequest(format!("Image too large (max {} MB)", MAX_IMAGE_SIZE / (1024 * 1024))));
+}
+
+/// Reject recognition while the gallery holds embeddings from a different recognizer,
+/// since comparing vectors of different lengths produces meaningless similarities
+fn ensure_gallery_compatible(state: &AppState) -> Result<(), AppError> {
+    let incompatible = state.incompatible_embeddings.load(Ordering::Relaxed);
+    if incompatible > 0 {
+        return Err(AppError::EmbeddingDimensionMismatch(format!(
+            "{} enrolled embeddings don't match the recognizer's {}-dimensional output; run /admin/reembed or re-enroll them after a model change",
+            incompatible,
+            state.recognizer_metadata.embedding_dim
+        )));
     }
+    Ok(())
+}
+
+// Enroll the single face in the image. Zero or multiple faces are rejected with 422.
+async fn enroll_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EnrollParams>,
+    upload: ImageUpload,
+) -> Result<(StatusCode, Json<PersonSummary>), AppError> {
+    let (name, image_bytes) = upload.into_enrollment(state.config.images.max_upload_bytes).await?;
+    let created = enroll_image(&state, name, &image_bytes, &params).await?;
 
-    let original_image = image::load_from_memory(&image_bytes)?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+/// Detect the single face in an uploaded image, embed it and store it under `name`.
+/// Shared by `/enroll` and the `import` command.
+pub async fn enroll_image(state: &Arc<AppState>, name: String, image_bytes: &[u8], params: &EnrollParams) -> Result<PersonSummary, AppError> {
+    validate_name(&name)?;
+    validate_group(state, params.group.as_deref())?;
+
+    let original_image = Arc::new(validate_and_decode_image(image_bytes, &state.config.images)?);
     let (original_w, original_h) = original_image.dimensions();
 
-    // Validate image dimensions
-    if original_w < MIN_IMAGE_DIMENSION || original_h < MIN_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too small (min {}x{})", MIN_IMAGE_DIMENSION, MIN_IMAGE_DIMENSION)));
+    let slot = inference_permit(state).await?;
+    let image = Arc::clone(&original_image);
+    let detection_params = params.detection_params();
+    let (mut faces, new_w, new_h) = run_inference(state, &slot, move |state| {
+        let detector_session_guard = state.detector_session.acquire();
+        detect_faces(&detector_session_guard, &image, &detection_params, &state.detector_metadata)
+    })
+    .await?;
+
+    match faces.len() {
+        0 => return Err(AppError::NoFaceDetected),
+        1 => {}
+        count => return Err(AppError::MultipleFacesDetected(count)),
+    }
+
+    let scale_w = original_w as f32 / new_w as f32;
+    let scale_h = original_h as f32 / new_h as f32;
+
+    let mut face = faces.swap_remove(0);
+    face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
+    ensure_frontal(state, &face, &name)?;
+    if let Some(pose) = estimate_pose(&face).filter(|pose| pose.frontalness < MIN_ENROLL_FRONTALNESS) {
+        tracing::warn!(
+            "Enrolling {} from a non-frontal face (yaw {:.0}°, pitch {:.0}°); the reference embedding may match poorly",
+            name, pose.yaw, pose.pitch
+        );
+    }
+
+    let image = Arc::clone(&original_image);
+    let query_face = face.clone();
+    let embedding = run_inference(state, &slot, move |state| {
+        let recognizer_session_guard = state.recognizer_session.acquire();
+        get_recognition_embedding(&recognizer_session_guard, &image, &query_face, &state.recognizer_metadata)
+    })
+    .await?;
+    drop(slot);
+
+    // Create gallery crop (256x256 square image)
+    let cropped_image = create_gallery_crop(&original_image, &face, 256)?;
+
+    ensure_embedding_dim(state, &embedding)?;
+    ensure_not_duplicate(state, &embedding, params).await?;
+    store_person(state, Person { name, embedding, cropped_image, group: params.group.clone(), created_at: None, updated_at: None }).await
+}
+
+/// With `recognition.require_frontal_enrollment`, reject faces whose estimated yaw or
+/// roll exceeds the configured limits. Faces without keypoints have no pose to check.
+fn ensure_frontal(state: &AppState, face: &DetectedFace, name: &str) -> Result<(), AppError> {
+    let recognition = &state.config.recognition;
+    if !recognition.require_frontal_enrollment {
+        return Ok(());
     }
-    if original_w > MAX_IMAGE_DIMENSION || original_h > MAX_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too large (max {}x{})", MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION)));
+    let Some(pose) = estimate_pose(face) else {
+        tracing::warn!("Cannot check that {} faces the camera: the detector provides no keypoints", name);
+        return Ok(());
+    };
+    if pose.yaw.abs() > recognition.max_enrollment_yaw || pose.roll.abs() > recognition.max_enrollment_roll {
+        return Err(AppError::NonFrontalFace { yaw: pose.yaw, pitch: pose.pitch, roll: pose.roll });
     }
+    Ok(())
+}
+
+async fn enroll_from_bbox_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EnrollParams>,
+    upload: ImageUpload,
+) -> Result<(StatusCode, Json<PersonSummary>), AppError> {
+    let (name, image_bytes, bbox) = upload.into_bbox_enrollment(state.config.images.max_upload_bytes).await?;
+
+    validate_name(&name)?;
+    validate_group(&state, params.group.as_deref())?;
+
+    let original_image = validate_and_decode_image(&image_bytes, &state.config.images)?;
+    let (original_w, original_h) = original_image.dimensions();
 
     // Validate bounding box coordinates
     if bbox[0] < 0.0 || bbox[1] < 0.0 || bbox[2] > original_w as f32 || bbox[3] > original_h as f32 {
@@ -185,220 +797,829 @@ async fn enroll_from_bbox_handler(
     };
 
     // Generate embedding directly from the bbox coordinates
-    let embedding = {
-        let mut recognizer_session_guard = state.recognizer_session.lock().unwrap();
-        get_recognition_embedding(&mut recognizer_session_guard, &original_image, &face, &state.recognizer_metadata)?
-    };
+    let slot = inference_permit(&state).await?;
+    let query_face = face.clone();
+    let (original_image, embedding) = run_inference(&state, &slot, move |state| {
+        let recognizer_session_guard = state.recognizer_session.acquire();
+        let embedding = get_recognition_embedding(&recognizer_session_guard, &original_image, &query_face, &state.recognizer_metadata)?;
+        Ok((original_image, embedding))
+    })
+    .await?;
+    drop(slot);
 
     // Create gallery crop (256x256 square image)
     let cropped_image = create_gallery_crop(&original_image, &face, 256)?;
 
-    let person = Person { name, embedding, cropped_image };
-    let _created_person: Option<Person> = state.db.create("person").content(person).await?;
+    ensure_embedding_dim(&state, &embedding)?;
+    ensure_not_duplicate(&state, &embedding, &params).await?;
+    let created = store_person(&state, Person { name, embedding, cropped_image, group: params.group, created_at: None, updated_at: None }).await?;
 
-    Ok(StatusCode::CREATED)
+    Ok((StatusCode::CREATED, Json(created)))
 }
 
+// Recognize every face in the image. An image without faces yields an empty array (200).
 async fn recognize_handler(
     State(state): State<Arc<AppState>>,
     Query(params): Query<DebugParams>,
-    multipart: Multipart,
-) -> Result<Json<Vec<RecognitionResult>>, AppError> {
-    let image_bytes = parse_recognize_multipart(multipart).await?;
-
-    // Validate image size
-    if image_bytes.is_empty() {
-        return Err(AppError::BadRequest("Image data is empty".to_string()));
-    }
-    if image_bytes.len() > MAX_IMAGE_SIZE {
-        return Err(AppError::BadRequest(format!("Image too large (max {} MB)", MAX_IMAGE_SIZE / (1024 * 1024))));
-    }
-
-    let original_image = image::load_from_memory(&image_bytes)?;
+    upload: ImageUpload,
+) -> Result<Response, AppError> {
+    let image_bytes = upload.into_image(state.config.images.max_upload_bytes).await?;
+    validate_group(&state, params.group.as_deref())?;
+    validate_stream(params.track.as_deref())?;
+    let mut timing = ServerTiming::default();
+
+    let decode_start = Instant::now();
+    let original_image = validate_and_decode_image(&image_bytes, &state.config.images)?;
     let (original_w, original_h) = original_image.dimensions();
-
-    // Validate image dimensions
-    if original_w < MIN_IMAGE_DIMENSION || original_h < MIN_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too small (min {}x{})", MIN_IMAGE_DIMENSION, MIN_IMAGE_DIMENSION)));
-    }
-    if original_w > MAX_IMAGE_DIMENSION || original_h > MAX_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too large (max {}x{})", MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION)));
+    timing.record("decode", decode_start.elapsed());
+
+    // Repeated uploads of an image are answered from the cache until the gallery changes.
+    // Annotated images aren't cached, and tracked frames depend on the stream's history.
+    let cache_start = Instant::now();
+    let cached = state
+        .cache
+        .as_ref()
+        .filter(|_| !params.annotate && params.track.is_none())
+        .map(|cache| (cache, recognition_cache_key(&original_image, &params), content_hash(&original_image), cache.generation()));
+    if let Some((cache, key, content_hash, _)) = &cached {
+        let results = cache.get(key, content_hash);
+        timing.record("cache", cache_start.elapsed());
+        if let Some(results) = results {
+            debug!("Answering recognition of {:016x} from the cache", key.image_hash);
+            return Ok(recognition_response(finish_recognition(&state, results).await, None, &timing));
+        }
     }
 
-    let (mut faces, new_w, new_h) = {
-        let mut detector_session_guard = state.detector_session.lock().unwrap();
-        detect_faces(&mut detector_session_guard, &image_bytes, &params, &state.detector_metadata)?
-    };
+    let original_image = Arc::new(original_image);
+    let slot = inference_permit(&state).await?;
+    let detection_start = Instant::now();
+    let (image, detection_params) = (Arc::clone(&original_image), params.clone());
+    let (mut faces, new_w, new_h) = run_inference(&state, &slot, move |state| {
+        let detector_session_guard = state.detector_session.acquire();
+        detect_faces(&detector_session_guard, &image, &detection_params, &state.detector_metadata)
+    })
+    .await?;
+    timing.record("detect", detection_start.elapsed());
     if faces.is_empty() {
-        return Ok(Json(Vec::new()));
+        if let Some(stream) = params.track.as_deref() {
+            track_faces(&state, stream, &[]);
+        }
+        if let Some((cache, key, content_hash, generation)) = cached {
+            cache.insert(key, content_hash, generation, Vec::new());
+        }
+        let encode_start = Instant::now();
+        let annotated_image = params.annotate.then(|| encode_jpeg_base64(&original_image)).transpose()?;
+        if annotated_image.is_some() {
+            timing.record("encode", encode_start.elapsed());
+        }
+        return Ok(recognition_response(Vec::new(), annotated_image, &timing));
     }
+    ensure_gallery_compatible(&state)?;
     let scale_w = original_w as f32 / new_w as f32;
     let scale_h = original_h as f32 / new_h as f32;
 
-    let mut results = Vec::new();
     for face in &mut faces {
         face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
-        let embedding = {
-            let mut recognizer_session_guard = state.recognizer_session.lock().unwrap();
-            get_recognition_embedding(&mut recognizer_session_guard, &original_image, &face, &state.recognizer_metadata)?
-        };
+    }
 
-        let mut response = state.db
-            .query("SELECT name, vector::similarity::cosine(embedding, $query) AS similarity FROM person ORDER BY similarity DESC LIMIT 1")
-            .bind(("query", embedding))
-            .await?;
+    // With ?track=<stream>, faces followed from earlier frames keep their identity and
+    // are only recognized again every `tracking.requery_interval` frames
+    let tracked = params.track.as_deref().map(|stream| track_faces(&state, stream, &faces));
 
-        if let Some(mut db_res) = response.take::<Option<RecognitionResult>>(0)? {
-            if db_res.similarity < 0.45 {
-                db_res.name = "Unknown".to_string();
+    // Marginal detections are reported as unknown without spending an embedding and a lookup on them
+    let min_score = params.recognize_min_score.unwrap_or(state.config.recognition.recognize_min_score);
+    let confident = faces.iter().filter(|face| face.score >= min_score).count();
+    if confident < faces.len() {
+        debug!("Skipping recognition of {} face(s) scoring below {}", faces.len() - confident, min_score);
+    }
+    // Nothing can match an empty gallery, so the recognizer and the lookups are skipped
+    let gallery_empty =
+        state.config.recognition.skip_empty_gallery && state.gallery_size.load(Ordering::Relaxed) == 0;
+    if gallery_empty {
+        debug!("Gallery is empty, reporting {} face(s) as unknown without recognition", faces.len());
+    }
+    let needs_query = |index: usize, face: &DetectedFace| {
+        !gallery_empty && face.score >= min_score && tracked.as_ref().is_none_or(|tracked| tracked[index].needs_query)
+    };
+    let query_faces: Vec<DetectedFace> = faces
+        .iter()
+        .enumerate()
+        .filter(|(index, face)| needs_query(*index, face))
+        .map(|(_, face)| face.clone())
+        .collect();
+    let embedding_start = Instant::now();
+    let image = Arc::clone(&original_image);
+    let mut embeddings = run_inference(&state, &slot, move |state| {
+        let recognizer_session_guard = state.recognizer_session.acquire();
+        get_recognition_embeddings(&recognizer_session_guard, &image, &query_faces, &state.recognizer_metadata)
+    })
+    .await?
+    .into_iter();
+    drop(slot);
+    timing.record("embed", embedding_start.elapsed());
+
+    let recognition = &state.config.recognition;
+    let unknown = |face: &DetectedFace| RecognitionResult {
+        name: recognition.unknown_label.clone(),
+        similarity: 0.0,
+        is_match: false,
+        bbox: Some(face.clamp_to_image(original_w, original_h)),
+        keypoints: face.keypoints(),
+        track_id: None,
+    };
+
+    let mut results = Vec::new();
+    // The best candidate of each face, drawn with `?annotate=true` whether it matched or not
+    let mut best_candidates = Vec::new();
+    // Whether each face was recognized on this frame
+    let mut queried = Vec::new();
+    // Time spent in gallery lookups, summed over the faces
+    let mut db_time = Duration::ZERO;
+    for (index, face) in faces.iter().enumerate() {
+        let embedding = if needs_query(index, face) { embeddings.next() } else { None };
+        queried.push(embedding.is_some());
+        let Some(embedding) = embedding else {
+            results.push(unknown(face));
+            best_candidates.push(None);
+            continue;
+        };
+        let db_query_start = Instant::now();
+        let candidates = find_top_matches(&state, embedding, params.group.as_deref()).await?;
+        db_time += db_query_start.elapsed();
+        let is_match = is_accepted_match(&candidates, recognition);
+
+        if let Some(mut db_res) = candidates.into_iter().next() {
+            best_candidates.push(Some((db_res.name.clone(), db_res.similarity)));
+            if !is_match && !recognition.report_best_candidate {
+                db_res.name = recognition.unknown_label.clone();
             }
-            db_res.bbox = Some(face.bbox);
+            db_res.is_match = is_match;
+            db_res.bbox = Some(face.clamp_to_image(original_w, original_h));
+            db_res.keypoints = face.keypoints();
             results.push(db_res);
         } else {
-            results.push(RecognitionResult {
-                name: "Unknown".to_string(),
-                similarity: 0.0,
-                bbox: Some(face.bbox),
-            });
+            results.push(unknown(face));
+            best_candidates.push(None);
         }
     }
 
-    Ok(Json(results))
+    if db_time > Duration::ZERO {
+        timing.record("db", db_time);
+    }
+
+    // Replace each tracked face's per-frame result with its track's smoothed identity
+    if let (Some(stream), Some(tracked)) = (params.track.as_deref(), &tracked) {
+        let mut trackers = state.trackers.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(tracker) = trackers.get_mut(stream) {
+            let faces = results.iter_mut().zip(&mut best_candidates).zip(tracked).zip(queried);
+            for (((result, candidate), tracked_face), queried) in faces {
+                let track_id = tracked_face.track_id;
+                if queried {
+                    let recognition = result.is_match.then(|| (result.name.clone(), result.similarity));
+                    tracker.record(track_id, recognition, &state.config.tracking);
+                }
+                result.track_id = Some(track_id);
+                match tracker.identity(track_id) {
+                    Some((name, similarity)) => {
+                        *candidate = Some((name.clone(), similarity));
+                        result.name = name;
+                        result.similarity = similarity;
+                        result.is_match = true;
+                    }
+                    // A match this frame that the track's history outvotes
+                    None if result.is_match => {
+                        result.is_match = false;
+                        if !recognition.report_best_candidate {
+                            result.name = recognition.unknown_label.clone();
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    if let Some((cache, key, content_hash, generation)) = cached {
+        cache.insert(key, content_hash, generation, results.clone());
+    }
+
+    // Every face is drawn, including unknown faces omitted from the results below
+    let annotated_image = if params.annotate {
+        let final_results: Vec<FinalResult> = faces
+            .iter()
+            .zip(&results)
+            .zip(best_candidates)
+            .map(|((face, result), candidate)| FinalResult {
+                detection: face.clone(),
+                recognition: candidate,
+                is_match: result.is_match,
+            })
+            .collect();
+        let mut image = Arc::unwrap_or_clone(original_image);
+        draw_detections(&mut image, &final_results, &state.font, &recognition.unknown_label, &state.config.drawing);
+        let encode_start = Instant::now();
+        let annotated_image = encode_jpeg_base64(&image)?;
+        timing.record("encode", encode_start.elapsed());
+        Some(annotated_image)
+    } else {
+        None
+    };
+
+    Ok(recognition_response(finish_recognition(&state, results).await, annotated_image, &timing))
+}
+
+/// Identify a recognition in the cache by the image and the parameters that change its results
+fn recognition_cache_key(image: &DynamicImage, params: &DebugParams) -> CacheKey {
+    CacheKey {
+        image_hash: perceptual_hash(image),
+        dimensions: image.dimensions(),
+        group: params.group.clone(),
+        threshold: params.threshold.map(f32::to_bits),
+        recognize_min_score: params.recognize_min_score.map(f32::to_bits),
+    }
+}
+
+/// Audit the results of every face, then shape them for the response
+async fn finish_recognition(state: &AppState, mut results: Vec<RecognitionResult>) -> Vec<RecognitionResult> {
+    let recognition = &state.config.recognition;
+    if let Some(audit) = &state.audit {
+        // A failed audit write shouldn't fail the recognition itself
+        if let Err(e) = audit.record(state.store.as_ref(), &results, &recognition.unknown_label).await {
+            tracing::error!("Failed to write audit log: {}", e);
+        }
+    }
+
+    // Unknown faces are still audited above, only left out of the response
+    if recognition.omit_unknown {
+        results.retain(|result| result.is_match);
+    }
+
+    // Thresholds and the audit log above use full precision; only the response is rounded
+    if let Some(decimals) = recognition.similarity_decimals {
+        for result in &mut results {
+            result.similarity = round_to_decimals(result.similarity, decimals);
+        }
+    }
+    results
+}
+
+/// Round `value` to `decimals` places, e.g. 0.6123199 to 0.612 with 3
+fn round_to_decimals(value: f32, decimals: u32) -> f32 {
+    let factor = 10f32.powi(decimals as i32);
+    (value * factor).round() / factor
+}
+
+/// Check a `?track=` stream name: 1-64 letters, digits, underscores or dashes
+fn validate_stream(stream: Option<&str>) -> Result<(), AppError> {
+    match stream {
+        Some(stream) if !is_valid_group(stream) => Err(AppError::BadRequest(format!(
+            "Invalid stream name {:?}: use 1-64 letters, digits, underscores or dashes",
+            stream
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Assign the faces of a frame of `stream` to its tracks, forgetting streams that have
+/// been idle for longer than `tracking.stream_idle_timeout_secs`
+fn track_faces(state: &AppState, stream: &str, faces: &[DetectedFace]) -> Vec<TrackedFace> {
+    let config = &state.config.tracking;
+    let idle_timeout = Duration::from_secs(config.stream_idle_timeout_secs);
+    let mut trackers = state.trackers.lock().unwrap_or_else(PoisonError::into_inner);
+    trackers.retain(|_, tracker| tracker.idle_for() < idle_timeout);
+    trackers.entry(stream.to_string()).or_default().update(faces, config)
+}
+
+/// The bare results, or with `?annotate=true` an object also carrying the drawn image
+fn recognition_response(results: Vec<RecognitionResult>, annotated_image: Option<String>, timing: &ServerTiming) -> Response {
+    let mut response = match annotated_image {
+        Some(annotated_image) => Json(AnnotatedRecognition { results, annotated_image }).into_response(),
+        None => Json(results).into_response(),
+    };
+    response.headers_mut().insert("server-timing", timing.header_value());
+    response
+}
+
+/// Durations of the stages a request went through, reported in the `Server-Timing`
+/// header so browser devtools and clients can see where the time went
+#[derive(Default)]
+struct ServerTiming(Vec<(&'static str, Duration)>);
+
+impl ServerTiming {
+    fn record(&mut self, stage: &'static str, duration: Duration) {
+        self.0.push((stage, duration));
+    }
+
+    /// The header value, e.g. `detect;dur=12.4, embed;dur=31.0` with durations in milliseconds
+    fn header_value(&self) -> HeaderValue {
+        let metrics = self
+            .0
+            .iter()
+            .map(|(stage, duration)| format!("{};dur={:.1}", stage, duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        HeaderValue::from_str(&metrics).unwrap()
+    }
+}
+
+/// Encode an image as a base64 JPEG for embedding in a JSON response
+fn encode_jpeg_base64(image: &DynamicImage) -> Result<String, AppError> {
+    // The JPEG encoder rejects alpha channels
+    let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    rgb.write_to(&mut buffer, image::ImageFormat::Jpeg)?;
+    Ok(general_purpose::STANDARD.encode(buffer.into_inner()))
+}
+
+// Detect faces without recognizing them: no recognizer run and no gallery lookup.
+// An image without faces yields an empty array (200).
+async fn detect_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DebugParams>,
+    multipart: Multipart,
+) -> Result<Json<Vec<Detection>>, AppError> {
+    let image_bytes = parse_recognize_multipart(multipart, state.config.images.max_upload_bytes).await?;
+
+    let original_image = validate_and_decode_image(&image_bytes, &state.config.images)?;
+    let (original_w, original_h) = original_image.dimensions();
+
+    let slot = inference_permit(&state).await?;
+    let (faces, new_w, new_h) = run_inference(&state, &slot, move |state| {
+        let detector_session_guard = state.detector_session.acquire();
+        detect_faces(&detector_session_guard, &original_image, &params, &state.detector_metadata)
+    })
+    .await?;
+    drop(slot);
+    let scale_w = original_w as f32 / new_w as f32;
+    let scale_h = original_h as f32 / new_h as f32;
+
+    let detections = faces
+        .into_iter()
+        .map(|mut face| {
+            face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
+            Detection { bbox: face.clamp_to_image(original_w, original_h), ..Detection::from(&face) }
+        })
+        .collect();
+    Ok(Json(detections))
+}
+
+// Group the faces found across several images by identity, without consulting the gallery.
+// Lets operators spot the same unknown person recurring across frames.
+async fn cluster_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DebugParams>,
+    multipart: Multipart,
+) -> Result<Json<ClusterResponse>, AppError> {
+    let images = parse_cluster_multipart(multipart, state.config.images.max_upload_bytes).await?;
+
+    let mut faces = Vec::new();
+    let mut embeddings = Vec::new();
+    for (image_index, image_bytes) in images.iter().enumerate() {
+        // Name the offending image, since several are uploaded at once
+        let original_image = validate_and_decode_image(image_bytes, &state.config.images).map_err(|e| match e {
+            AppError::InvalidImage(msg) => AppError::InvalidImage(format!("Image {}: {}", image_index, msg)),
+            AppError::ImageTooLarge(msg) => AppError::ImageTooLarge(format!("Image {}: {}", image_index, msg)),
+            AppError::PayloadTooLarge(msg) => AppError::PayloadTooLarge(format!("Image {}: {}", image_index, msg)),
+            other => other,
+        })?;
+        let (original_w, original_h) = original_image.dimensions();
+
+        let slot = inference_permit(&state).await?;
+        let detection_params = params.clone();
+        let embedded = run_inference(&state, &slot, move |state| {
+            let (detected, new_w, new_h) = {
+                let detector_session_guard = state.detector_session.acquire();
+                detect_faces(&detector_session_guard, &original_image, &detection_params, &state.detector_metadata)?
+            };
+            let scale_w = original_w as f32 / new_w as f32;
+            let scale_h = original_h as f32 / new_h as f32;
+
+            let mut embedded = Vec::new();
+            for mut face in detected {
+                face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
+                let embedding = {
+                    let recognizer_session_guard = state.recognizer_session.acquire();
+                    get_recognition_embedding(&recognizer_session_guard, &original_image, &face, &state.recognizer_metadata)?
+                };
+                embedded.push((face, embedding));
+            }
+            Ok(embedded)
+        })
+        .await?;
+        drop(slot);
+
+        for (face, embedding) in embedded {
+            faces.push((image_index, face.clamp_to_image(original_w, original_h)));
+            embeddings.push(embedding);
+        }
+    }
+
+    let labels = cluster_embeddings(&embeddings, state.config.recognition.cluster_distance_threshold);
+    let clusters = labels.iter().max().map_or(0, |&max| max + 1);
+    debug!("Clustered {} faces from {} images into {} clusters", faces.len(), images.len(), clusters);
+
+    let faces = faces
+        .into_iter()
+        .zip(labels)
+        .map(|((image, bbox), cluster)| ClusteredFace { image, bbox, cluster })
+        .collect();
+    Ok(Json(ClusterResponse { clusters, faces }))
 }
 
+// Render detections onto the image. Without faces the image is returned unannotated
+// (apart from the `?grid=` overlay); the `X-Faces-Detected` header carries the face count
+// either way. `?stages=` selects which optional stages run, and `X-Stage-Timings` reports
+// the time of each executed one. `Server-Timing` carries the same times at sub-millisecond
+// precision, with recognition split into embedding and gallery lookups.
 async fn debug_detector_handler(
     State(state): State<Arc<AppState>>,
     Query(params): Query<DebugParams>,
     multipart: Multipart,
 ) -> Result<(HeaderMap, Vec<u8>), AppError> {
     let request_start_time = Instant::now();
+    let stages = parse_debug_stages(params.stages.as_deref())?;
+    validate_group(&state, params.group.as_deref())?;
+    if let Some(stride) = params.grid {
+        if !state.detector_metadata.stride_output_mapping.contains_key(&stride) {
+            let mut strides: Vec<i32> = state.detector_metadata.stride_output_mapping.keys().copied().collect();
+            strides.sort();
+            return Err(AppError::BadRequest(format!("Unknown grid stride {} (the detector uses {:?})", stride, strides)));
+        }
+    }
+    let mut timings: Vec<(&str, u128)> = Vec::new();
+    let mut server_timing = ServerTiming::default();
 
     // --- 1. Image Loading & Parsing ---
     let image_load_start = Instant::now();
-    let image_bytes = parse_recognize_multipart(multipart).await?;
-
-    // Validate image size
-    if image_bytes.is_empty() {
-        return Err(AppError::BadRequest("Image data is empty".to_string()));
-    }
-    if image_bytes.len() > MAX_IMAGE_SIZE {
-        return Err(AppError::BadRequest(format!("Image too large (max {} MB)", MAX_IMAGE_SIZE / (1024 * 1024))));
-    }
+    let image_bytes = parse_recognize_multipart(multipart, state.config.images.max_upload_bytes).await?;
 
-    let mut image = image::load_from_memory(&image_bytes)?;
+    let image = Arc::new(validate_and_decode_image(&image_bytes, &state.config.images)?);
     let (original_w, original_h) = image.dimensions();
-
-    // Validate image dimensions
-    if original_w < MIN_IMAGE_DIMENSION || original_h < MIN_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too small (min {}x{})", MIN_IMAGE_DIMENSION, MIN_IMAGE_DIMENSION)));
-    }
-    if original_w > MAX_IMAGE_DIMENSION || original_h > MAX_IMAGE_DIMENSION {
-        return Err(AppError::BadRequest(format!("Image too large (max {}x{})", MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION)));
-    }
     debug!("Image loaded in {} ms", image_load_start.elapsed().as_millis());
+    timings.push(("decode", image_load_start.elapsed().as_millis()));
+    server_timing.record("decode", image_load_start.elapsed());
 
     // --- 2. Detect all faces in the image ---
     let detection_start = Instant::now();
-    let (detected_faces, new_w, new_h) = {
-        let mut detector_session_guard = state.detector_session.lock().unwrap();
-        detect_faces(&mut detector_session_guard, &image_bytes, &params, &state.detector_metadata)?
-    };
+    let slot = inference_permit(&state).await?;
+    let (detection_image, detection_params) = (Arc::clone(&image), params.clone());
+    let (detected_faces, new_w, new_h) = run_inference(&state, &slot, move |state| {
+        let detector_session_guard = state.detector_session.acquire();
+        detect_faces(&detector_session_guard, &detection_image, &detection_params, &state.detector_metadata)
+    })
+    .await?;
     debug!("Face detection completed in {} ms", detection_start.elapsed().as_millis());
+    timings.push(("detect", detection_start.elapsed().as_millis()));
+    server_timing.record("detect", detection_start.elapsed());
+    if stages.recognize && !detected_faces.is_empty() {
+        ensure_gallery_compatible(&state)?;
+    }
 
+    let faces_detected = detected_faces.len();
     let mut final_results = Vec::new();
 
-    // 2. For each detected face, run recognition
-    let faces_recognition_start = Instant::now();
     let scale_w = original_w as f32 / new_w as f32;
     let scale_h = original_h as f32 / new_h as f32;
 
-    for face in detected_faces {
-        let result = process_detected_face(&state, face, &image, scale_w, scale_h).await?;
-        final_results.push(result);
+    // Scale coordinates back to original image space; faces falling outside the image
+    // are drawn but not recognized
+    let (faces, mut unrecognized): (Vec<_>, Vec<_>) = detected_faces
+        .into_iter()
+        .map(|mut face| {
+            face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
+            face
+        })
+        .partition(|face| face.validate_bounds(original_w, original_h));
+    if !unrecognized.is_empty() {
+        debug!("{} face(s) out of bounds, skipping recognition", unrecognized.len());
     }
-    debug!("All faces processed in {} ms", faces_recognition_start.elapsed().as_millis());
 
-    // 3. Draw the final results (boxes, dots, AND labels)
-    let draw_start = Instant::now();
-    draw_detections(&mut image, &final_results, &state.font);
-    debug!("Drawing completed in {} ms", draw_start.elapsed().as_millis());
+    // --- 3. Recognize the faces ---
+    if stages.recognize {
+        let faces_recognition_start = Instant::now();
+        let (recognition_image, query_faces) = (Arc::clone(&image), faces.clone());
+        let embeddings = run_inference(&state, &slot, move |state| {
+            let recognizer_session_guard = state.recognizer_session.acquire();
+            get_recognition_embeddings(&recognizer_session_guard, &recognition_image, &query_faces, &state.recognizer_metadata)
+        })
+        .await?;
+        drop(slot);
+        debug!("Face embeddings computed in {} ms", faces_recognition_start.elapsed().as_millis());
+        server_timing.record("embed", faces_recognition_start.elapsed());
 
-    // 4. Encode and return the image
+        let db_start = Instant::now();
+        for (face, embedding) in faces.into_iter().zip(embeddings) {
+            final_results.push(recognize_embedded_face(&state, face, embedding, params.group.as_deref()).await?);
+        }
+        server_timing.record("db", db_start.elapsed());
+        debug!("All faces processed in {} ms", faces_recognition_start.elapsed().as_millis());
+        timings.push(("recognize", faces_recognition_start.elapsed().as_millis()));
+    } else {
+        drop(slot);
+        unrecognized.extend(faces);
+    }
+    final_results.extend(unrecognized.into_iter().map(|face| FinalResult { detection: face, recognition: None, is_match: false }));
+
+    // --- 4. Draw the final results (boxes, dots, AND labels) ---
+    let mut image = Arc::unwrap_or_clone(image);
+    if stages.draw {
+        let draw_start = Instant::now();
+        if let Some(stride) = params.grid {
+            draw_anchor_grid(&mut image, stride, &state.detector_metadata, scale_w, scale_h);
+        }
+        draw_detections(&mut image, &final_results, &state.font, &state.config.recognition.unknown_label, &state.config.drawing);
+        if params.keypoints {
+            draw_keypoint_labels(&mut image, &final_results, &state.font);
+        }
+        debug!("Drawing completed in {} ms", draw_start.elapsed().as_millis());
+        timings.push(("draw", draw_start.elapsed().as_millis()));
+        server_timing.record("draw", draw_start.elapsed());
+    }
+
+    // --- 5. Encode and return the image ---
     let encode_start = Instant::now();
     let mut buffer = std::io::Cursor::new(Vec::new());
     image.write_to(&mut buffer, image::ImageFormat::Png)?;
     let response_bytes = buffer.into_inner();
     debug!("Image encoding completed in {} ms", encode_start.elapsed().as_millis());
+    timings.push(("encode", encode_start.elapsed().as_millis()));
+    server_timing.record("encode", encode_start.elapsed());
+
+    let stage_timings = timings
+        .iter()
+        .map(|(stage, ms)| format!("{}={}", stage, ms))
+        .collect::<Vec<_>>()
+        .join(", ");
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, "image/png".parse().unwrap());
+    headers.insert("x-faces-detected", HeaderValue::from(faces_detected));
+    headers.insert("x-stage-timings", HeaderValue::from_str(&stage_timings).unwrap());
+    headers.insert("server-timing", server_timing.header_value());
     debug!("Total request time: {} ms", request_start_time.elapsed().as_millis());
     debug!("--------------------------");
     Ok((headers, response_bytes))
 }
 
-/// Process a single detected face: scale coordinates, generate embedding, and query database
-async fn process_detected_face(
+/// Optional stages of `/debug/detector`; detection and encoding always run
+struct DebugStages {
+    recognize: bool,
+    draw: bool,
+}
+
+/// Parse a comma-separated `stages` list such as "detect,draw". All stages run when absent.
+fn parse_debug_stages(stages: Option<&str>) -> Result<DebugStages, AppError> {
+    let Some(stages) = stages else {
+        return Ok(DebugStages { recognize: true, draw: true });
+    };
+    let mut selected = DebugStages { recognize: false, draw: false };
+    for stage in stages.split(',').map(str::trim).filter(|stage| !stage.is_empty()) {
+        match stage {
+            "detect" => {}
+            "recognize" => selected.recognize = true,
+            "draw" => selected.draw = true,
+            other => {
+                return Err(AppError::BadRequest(format!(
+                    "Unknown stage {:?} (expected detect, recognize or draw)",
+                    other
+                )))
+            }
+        }
+    }
+    Ok(selected)
+}
+
+// Render the recognizer input for the largest face back into an image, so alignment and
+// normalization problems can be inspected. The raw embedding norm is returned in the
+// `X-Embedding-Norm` header.
+async fn debug_recognizer_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DebugParams>,
+    multipart: Multipart,
+) -> Result<(HeaderMap, Vec<u8>), AppError> {
+    let image_bytes = parse_recognize_multipart(multipart, state.config.images.max_upload_bytes).await?;
+
+    let original_image = validate_and_decode_image(&image_bytes, &state.config.images)?;
+    let (original_w, original_h) = original_image.dimensions();
+
+    let slot = inference_permit(&state).await?;
+    let (crop, embedding) = run_inference(&state, &slot, move |state| {
+        let (faces, new_w, new_h) = {
+            let detector_session_guard = state.detector_session.acquire();
+            detect_faces(&detector_session_guard, &original_image, &params, &state.detector_metadata)?
+        };
+
+        let scale_w = original_w as f32 / new_w as f32;
+        let scale_h = original_h as f32 / new_h as f32;
+
+        // Pick the largest face by bounding box area
+        let mut face = faces
+            .into_iter()
+            .max_by(|a, b| bbox_area(&a.bbox).total_cmp(&bbox_area(&b.bbox)))
+            .ok_or(AppError::NoFaceDetected)?;
+        face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
+
+        let input_tensor = prepare_recognition_input(&original_image, &face, &state.recognizer_metadata);
+        let crop = tensor_to_image(&input_tensor, &state.recognizer_metadata.normalization);
+        let embedding = {
+            let recognizer_session_guard = state.recognizer_session.acquire();
+            run_recognizer(&recognizer_session_guard, input_tensor, &state.recognizer_metadata)?
+        };
+        Ok((crop, embedding))
+    })
+    .await?;
+    drop(slot);
+    let embedding_norm = l2_norm(&embedding);
+    debug!("Recognizer input rendered, raw embedding norm {:.4}", embedding_norm);
+
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(crop).write_to(&mut buffer, image::ImageFormat::Png)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "image/png".parse().unwrap());
+    headers.insert("x-embedding-norm", HeaderValue::from_str(&format!("{:.6}", embedding_norm)).unwrap());
+    Ok((headers, buffer.into_inner()))
+}
+
+fn bbox_area(bbox: &[f32; 4]) -> f32 {
+    (bbox[2] - bbox[0]) * (bbox[3] - bbox[1])
+}
+
+/// Look up an already embedded face in the gallery
+async fn recognize_embedded_face(
     state: &AppState,
-    mut face: DetectedFace,
-    original_image: &DynamicImage,
-    scale_w: f32,
-    scale_h: f32,
+    face: DetectedFace,
+    embedding: Vec<f32>,
+    group: Option<&str>,
 ) -> Result<FinalResult, AppError> {
-    let face_recognition_start = Instant::now();
+    let db_query_start = Instant::now();
+    let candidates = find_top_matches(state, embedding, group).await?;
+    debug!("DB query completed in {} ms", db_query_start.elapsed().as_millis());
 
-    // Scale coordinates back to original image space
-    face.scale_to_original(scale_w, scale_h, X_OFFSET, Y_OFFSET);
+    let is_match = is_accepted_match(&candidates, &state.config.recognition);
+    let recognition = candidates.into_iter().next().map(|r| (r.name, r.similarity));
+
+    Ok(FinalResult { detection: face, recognition, is_match })
+}
 
-    // Validate that the face coordinates are within image bounds
-    let (image_width, image_height) = original_image.dimensions();
-    if !face.validate_bounds(image_width, image_height) {
-        debug!("Face coordinates are out of bounds, skipping recognition");
-        return Ok(FinalResult { detection: face, recognition: None });
+/// The two closest entries of the `group` gallery to `embedding`, best first. The
+/// runner-up is only needed for the match margin check. The store skips records of a
+/// different embedding dimension, which may have been enrolled after
+/// `ensure_gallery_compatible` last counted them.
+async fn find_top_matches(state: &AppState, embedding: Vec<f32>, group: Option<&str>) -> Result<Vec<RecognitionResult>, AppError> {
+    state.store.query_nearest(&embedding, 2, group).await
+}
+
+/// Whether the best of `candidates` is accepted as a match: at least as similar as
+/// `match_threshold` and not within `match_margin` of the runner-up
+fn is_accepted_match(candidates: &[RecognitionResult], recognition: &RecognitionConfig) -> bool {
+    let Some(best) = candidates.first() else {
+        return false;
+    };
+    if is_ambiguous_match(candidates, recognition.match_margin) {
+        debug!("Best match is within the configured margin of the runner-up, treating as unknown");
+        return false;
     }
+    best.similarity >= recognition.match_threshold
+}
 
-    // Generate embedding
-    let embedding_start = Instant::now();
-    let embedding = {
-        let mut recognizer_session_guard = state.recognizer_session.lock().unwrap();
-        get_recognition_embedding(&mut recognizer_session_guard, original_image, &face, &state.recognizer_metadata)?
+/// Whether the best candidate fails to beat the runner-up by at least `margin`
+fn is_ambiguous_match(candidates: &[RecognitionResult], margin: f32) -> bool {
+    match candidates {
+        [best, second, ..] => margin > 0.0 && best.similarity - second.similarity < margin,
+        _ => false,
+    }
+}
+
+/// An uploaded image with its form fields: a multipart form, or a JSON body
+/// ([`ImageUploadBody`]) with the image base64 encoded, chosen by `Content-Type`
+enum ImageUpload {
+    Multipart(Multipart),
+    Json(ImageUploadBody),
+}
+
+impl<S: Send + Sync> FromRequest<S> for ImageUpload {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+        if !is_json {
+            return Multipart::from_request(request, state)
+                .await
+                .map(ImageUpload::Multipart)
+                .map_err(IntoResponse::into_response);
+        }
+        Json::<ImageUploadBody>::from_request(request, state)
+            .await
+            .map(|Json(body)| ImageUpload::Json(body))
+            .map_err(|e| {
+                let error = if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                    AppError::PayloadTooLarge(format!("Request body too large: {}", e.body_text()))
+                } else {
+                    AppError::BadRequest(e.body_text())
+                };
+                error.into_response()
+            })
+    }
+}
+
+impl ImageUpload {
+    /// The image of `/recognize`
+    async fn into_image(self, max_image_bytes: usize) -> Result<Vec<u8>, AppError> {
+        match self {
+            ImageUpload::Multipart(multipart) => parse_recognize_multipart(multipart, max_image_bytes).await,
+            ImageUpload::Json(body) => decode_image_base64(&body.image_base64),
+        }
+    }
+
+    /// The name and image of `/enroll`
+    async fn into_enrollment(self, max_image_bytes: usize) -> Result<(String, Vec<u8>), AppError> {
+        match self {
+            ImageUpload::Multipart(multipart) => parse_enroll_multipart(multipart, max_image_bytes).await,
+            ImageUpload::Json(body) => {
+                let name = body.name.ok_or_else(|| AppError::MissingMultipartField("name".to_string()))?;
+                Ok((name, decode_image_base64(&body.image_base64)?))
+            }
+        }
+    }
+
+    /// The name, image and face box of `/enroll-from-bbox`
+    async fn into_bbox_enrollment(self, max_image_bytes: usize) -> Result<(String, Vec<u8>, [f32; 4]), AppError> {
+        match self {
+            ImageUpload::Multipart(multipart) => parse_enroll_bbox_multipart(multipart, max_image_bytes).await,
+            ImageUpload::Json(body) => {
+                let name = body.name.ok_or_else(|| AppError::MissingMultipartField("name".to_string()))?;
+                let bbox = body.bbox.ok_or_else(|| AppError::MissingMultipartField("bbox".to_string()))?;
+                Ok((name, decode_image_base64(&body.image_base64)?, bbox))
+            }
+        }
+    }
+}
+
+/// Decode a base64 image from a JSON upload, with or without a `data:image/...;base64,` prefix
+fn decode_image_base64(encoded: &str) -> Result<Vec<u8>, AppError> {
+    let encoded = match encoded.split_once(";base64,") {
+        Some((prefix, data)) if prefix.starts_with("data:") => data,
+        _ => encoded,
     };
-    debug!("Face embedding computed in {} ms", embedding_start.elapsed().as_millis());
+    general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| AppError::BadRequest(format!("image_base64 is not valid base64: {}", e)))
+}
 
-    // Query database for recognition
-    let db_query_start = Instant::now();
-    let mut response = state.db
-        .query("SELECT name, vector::similarity::cosine(embedding, $query) AS similarity FROM person ORDER BY similarity DESC LIMIT 1")
-        .bind(("query", embedding))
-        .await?;
-    debug!("DB query completed in {} ms", db_query_start.elapsed().as_millis());
+/// Map a multipart read error, reporting a body over the `DefaultBodyLimit` as 413
+fn multipart_error(what: &str, e: MultipartError) -> AppError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::PayloadTooLarge(format!("Request body too large: {}", e.body_text()))
+    } else {
+        AppError::BadRequest(format!("Failed to read {}: {}", what, e))
+    }
+}
 
-    let recognition: Option<(String, f32)> = response.take::<Option<RecognitionResult>>(0)?
-        .map(|r| (r.name, r.similarity));
+/// Read one multipart field, rejecting it as soon as it grows past `limit` bytes rather
+/// than buffering everything the body limit allows
+async fn read_field(mut field: Field<'_>, what: &str, limit: usize) -> Result<Vec<u8>, AppError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(|e| multipart_error(what, e))? {
+        if bytes.len() + chunk.len() > limit {
+            return Err(AppError::PayloadTooLarge(format!("The {} exceeds {} bytes", what, limit)));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
 
-    debug!("Face recognition completed in {} ms", face_recognition_start.elapsed().as_millis());
+/// Read a short text field such as `name` or `bbox`, limited to `MAX_TEXT_FIELD_BYTES`
+async fn read_text_field(field: Field<'_>, what: &str) -> Result<String, AppError> {
+    let bytes = read_field(field, what, MAX_TEXT_FIELD_BYTES).await?;
+    String::from_utf8(bytes).map_err(|_| AppError::BadRequest(format!("The {} is not valid UTF-8", what)))
+}
 
-    Ok(FinalResult { detection: face, recognition })
+/// Store a field's value, rejecting a second field of the same name instead of letting
+/// the order of the form decide which one is used
+fn set_once<T>(slot: &mut Option<T>, field_name: &str, value: T) -> Result<(), AppError> {
+    if slot.replace(value).is_some() {
+        return Err(AppError::BadRequest(format!("Duplicate field: {}", field_name)));
+    }
+    Ok(())
 }
 
 async fn parse_enroll_multipart(
     mut multipart: Multipart,
+    max_image_bytes: usize,
 ) -> Result<(String, Vec<u8>), AppError> {
     let mut name = None;
     let mut image_bytes = None;
 
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
-        AppError::BadRequest(format!("Failed to read multipart field: {}", e))
-    })? {
+    while let Some(field) = multipart.next_field().await.map_err(|e| multipart_error("multipart field", e))? {
         let field_name = field.name().unwrap_or("").to_string();
 
         if field_name == "name" {
-            name = Some(field.text().await.map_err(|e| {
-                AppError::BadRequest(format!("Failed to read name field: {}", e))
-            })?);
+            set_once(&mut name, &field_name, read_text_field(field, "name field").await?)?;
         } else if field_name == "image" {
-            image_bytes = Some(field.bytes().await.map_err(|e| {
-                AppError::BadRequest(format!("Failed to read image field: {}", e))
-            })?.to_vec());
+            set_once(&mut image_bytes, &field_name, read_field(field, "image field", max_image_bytes).await?)?;
         }
     }
 
@@ -407,46 +1628,53 @@ async fn parse_enroll_multipart(
     Ok((name, image_bytes))
 }
 
-async fn parse_recognize_multipart(mut multipart: Multipart) -> Result<Vec<u8>, AppError> {
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
-        AppError::BadRequest(format!("Failed to read multipart field: {}", e))
-    })? {
+async fn parse_recognize_multipart(mut multipart: Multipart, max_image_bytes: usize) -> Result<Vec<u8>, AppError> {
+    let mut image_bytes = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| multipart_error("multipart field", e))? {
         if field.name().unwrap_or("") == "image" {
-            return Ok(field.bytes().await.map_err(|e| {
-                AppError::BadRequest(format!("Failed to read image field: {}", e))
-            })?.to_vec());
+            set_once(&mut image_bytes, "image", read_field(field, "image field", max_image_bytes).await?)?;
         }
     }
-    Err(AppError::MissingMultipartField("image".to_string()))
+    image_bytes.ok_or_else(|| AppError::MissingMultipartField("image".to_string()))
+}
+
+/// Collect every `image` field, in upload order
+async fn parse_cluster_multipart(mut multipart: Multipart, max_image_bytes: usize) -> Result<Vec<Vec<u8>>, AppError> {
+    let mut images = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(|e| multipart_error("multipart field", e))? {
+        if field.name().unwrap_or("") == "image" {
+            if images.len() == MAX_CLUSTER_IMAGES {
+                return Err(AppError::BadRequest(format!("Too many images (max {})", MAX_CLUSTER_IMAGES)));
+            }
+            images.push(read_field(field, "image field", max_image_bytes).await?);
+        }
+    }
+    if images.is_empty() {
+        return Err(AppError::MissingMultipartField("image".to_string()));
+    }
+    Ok(images)
 }
 
 async fn parse_enroll_bbox_multipart(
     mut multipart: Multipart,
+    max_image_bytes: usize,
 ) -> Result<(String, Vec<u8>, [f32; 4]), AppError> {
     let mut name = None;
     let mut image_bytes = None;
     let mut bbox = None;
 
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
-        AppError::BadRequest(format!("Failed to read multipart field: {}", e))
-    })? {
+    while let Some(field) = multipart.next_field().await.map_err(|e| multipart_error("multipart field", e))? {
         let field_name = field.name().unwrap_or("").to_string();
 
         match field_name.as_str() {
             "name" => {
-                name = Some(field.text().await.map_err(|e| {
-                    AppError::BadRequest(format!("Failed to read name field: {}", e))
-                })?);
+                set_once(&mut name, &field_name, read_text_field(field, "name field").await?)?;
             }
             "image" => {
-                image_bytes = Some(field.bytes().await.map_err(|e| {
-                    AppError::BadRequest(format!("Failed to read image field: {}", e))
-                })?.to_vec());
+                set_once(&mut image_bytes, &field_name, read_field(field, "image field", max_image_bytes).await?)?;
             }
             "bbox" => {
-                let bbox_str = field.text().await.map_err(|e| {
-                    AppError::BadRequest(format!("Failed to read bbox field: {}", e))
-                })?;
+                let bbox_str = read_text_field(field, "bbox field").await?;
                 // Parse bbox as "x1,y1,x2,y2"
                 let coords: Result<Vec<f32>, _> = bbox_str
                     .split(',')
@@ -454,7 +1682,7 @@ async fn parse_enroll_bbox_multipart(
                     .collect();
                 match coords {
                     Ok(coords) if coords.len() == 4 => {
-                        bbox = Some([coords[0], coords[1], coords[2], coords[3]]);
+                        set_once(&mut bbox, &field_name, [coords[0], coords[1], coords[2], coords[3]])?;
                     }
                     _ => return Err(AppError::BadRequest("Invalid bbox format. Expected: x1,y1,x2,y2".to_string())),
                 }
@@ -468,4 +1696,53 @@ async fn parse_enroll_bbox_multipart(
     let bbox = bbox.ok_or_else(|| AppError::MissingMultipartField("bbox".to_string()))?;
 
     Ok((name, image_bytes, bbox))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(similarities: &[f32]) -> Vec<RecognitionResult> {
+        similarities
+            .iter()
+            .map(|&similarity| RecognitionResult {
+                name: "Alice".to_string(),
+                similarity,
+                is_match: false,
+                bbox: None,
+                keypoints: None,
+                track_id: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_match_exactly_at_the_threshold_is_accepted() {
+        let recognition = RecognitionConfig { match_threshold: 0.5, ..RecognitionConfig::default() };
+        assert!(is_accepted_match(&candidates(&[0.5]), &recognition));
+        assert!(!is_accepted_match(&candidates(&[0.5 - 1e-6]), &recognition));
+        assert!(!is_accepted_match(&[], &recognition));
+    }
+
+    #[test]
+    fn a_lead_exactly_at_the_margin_is_accepted() {
+        let recognition = RecognitionConfig { match_threshold: 0.5, match_margin: 0.25, ..RecognitionConfig::default() };
+        assert!(is_accepted_match(&candidates(&[0.75, 0.5]), &recognition));
+        assert!(!is_accepted_match(&candidates(&[0.75, 0.5 + 1e-6]), &recognition));
+        // Two equally close candidates are ambiguous under a margin, but not without one
+        assert!(!is_accepted_match(&candidates(&[0.75, 0.75]), &recognition));
+        let recognition = RecognitionConfig { match_margin: 0.0, ..recognition };
+        assert!(is_accepted_match(&candidates(&[0.75, 0.75]), &recognition));
+    }
+
+    #[test]
+    fn complete_lines_are_split_off_and_the_rest_kept() {
+        let mut pending = b"{\"a\":1}\n\n{\"b\":2}\n{\"c\"".to_vec();
+        let lines = take_lines(&mut pending);
+        assert_eq!(lines, vec![b"{\"a\":1}".to_vec(), Vec::new(), b"{\"b\":2}".to_vec()]);
+        assert_eq!(pending, b"{\"c\"");
+
+        assert!(take_lines(&mut pending).is_empty());
+        assert_eq!(pending, b"{\"c\"");
+    }
+}
@@ -0,0 +1,75 @@
+use crate::error::AppError;
+use crate::handlers::enroll_image;
+use crate::models::EnrollParams;
+use crate::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Enroll every image below `dir`, taking each subdirectory's name as the person's name,
+/// and print a summary. Images that fail (no face, several faces, unreadable file, ...)
+/// are reported and skipped. An image matching an already enrolled person of the same
+/// name is counted as already enrolled, so an interrupted import can simply be re-run.
+pub async fn run(state: &Arc<AppState>, dir: &Path, force: bool, group: Option<String>) -> anyhow::Result<()> {
+    let params = EnrollParams { threshold: None, force, group };
+    let mut enrolled = 0;
+    let mut already_enrolled = 0;
+    let mut failures: Vec<(PathBuf, String)> = Vec::new();
+
+    for (name, images) in labeled_images(dir)? {
+        for path in images {
+            let result = match tokio::fs::read(&path).await {
+                Ok(bytes) => enroll_image(state, name.clone(), &bytes, &params).await,
+                Err(e) => {
+                    failures.push((path, e.to_string()));
+                    continue;
+                }
+            };
+            match result {
+                Ok(created) => {
+                    enrolled += 1;
+                    println!("enrolled {} ({}) from {}", created.name, created.id, path.display());
+                }
+                Err(AppError::DuplicatePerson { name: existing, .. }) if existing == name => {
+                    already_enrolled += 1;
+                    println!("skipped {}: {} is already enrolled", path.display(), name);
+                }
+                Err(e) => {
+                    println!("failed {}: {}", path.display(), e);
+                    failures.push((path, e.to_string()));
+                }
+            }
+        }
+    }
+
+    println!();
+    println!("{} enrolled, {} already enrolled, {} failed", enrolled, already_enrolled, failures.len());
+    for (path, reason) in &failures {
+        println!("  {}: {}", path.display(), reason);
+    }
+    Ok(())
+}
+
+/// The person subdirectories of `dir` with their files, both sorted by name
+fn labeled_images(dir: &Path) -> anyhow::Result<Vec<(String, Vec<PathBuf>)>> {
+    let mut persons = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| anyhow::anyhow!("Could not read {:?}: {}", dir, e))? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            tracing::warn!("Skipping {:?}: directory name is not valid UTF-8", path);
+            continue;
+        };
+        let name = name.to_string();
+
+        let mut images: Vec<PathBuf> = std::fs::read_dir(&path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        images.sort();
+        persons.push((name, images));
+    }
+    persons.sort();
+    Ok(persons)
+}
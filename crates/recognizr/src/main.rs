@@ -1,43 +1,77 @@
-use ort::{execution_providers::CUDAExecutionProvider, session::{builder::SessionBuilder, Session}};
+#[cfg(feature = "cuda")]
+use ort::execution_providers::{CUDAExecutionProvider, ExecutionProvider};
+use ort::session::{builder::SessionBuilder, Session};
 use ab_glyph::FontArc;
-use std::{fs, sync::{Arc, Mutex}};
-use surrealdb::{
-    engine::remote::ws::{Client, Ws},
-    opt::auth::Root,
-    Surreal,
-};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use std::{collections::HashMap, fs, net::SocketAddr, path::Path, sync::{atomic::AtomicUsize, Arc, Mutex}};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod audit;
+mod auth;
+mod cache;
+mod cli;
 mod config;
+mod db;
 mod error;
 mod handlers;
+mod import;
 mod models;
+mod negotiate;
 mod pipeline;
+mod rate_limit;
+mod session;
+mod store;
+mod timeout;
+#[cfg(test)]
+mod tests;
 
-use config::{ModelMetadata, DetectorMetadata, extract_detector_metadata, extract_recognizer_metadata, create_detector_metadata_with_mappings};
+use config::{ModelMetadata, DetectorMetadata, SessionOptions, StorageBackend, extract_detector_metadata, extract_recognizer_metadata, create_detector_metadata_with_mappings};
 
 pub struct AppState {
-    db: Surreal<Client>,
-    detector_session: Mutex<Session>,
-    recognizer_session: Mutex<Session>,
+    store: Box<dyn store::GalleryStore>,
+    detector_session: session::SharedSession,
+    recognizer_session: session::SharedSession,
     font: FontArc,
     detector_metadata: DetectorMetadata,
     recognizer_metadata: ModelMetadata,
+    config: config::Configuration,
+    /// Number of enrolled persons, refreshed on enrollment and `/admin/reload`
+    gallery_size: AtomicUsize,
+    /// Number of enrolled embeddings whose dimension doesn't match the recognizer
+    incompatible_embeddings: AtomicUsize,
+    rate_limiter: Option<rate_limit::RateLimiter>,
+    /// Slots bounding concurrent inference, when `models.max_concurrent_inferences` is set
+    inference_slots: Option<Arc<tokio::sync::Semaphore>>,
+    audit: Option<audit::AuditLog>,
+    /// Face trackers of the video streams recognized with `?track=<stream>`, by stream
+    trackers: Mutex<HashMap<String, pipeline::FaceTracker>>,
+    /// Recent recognition results, when `cache.enabled` is set
+    cache: Option<cache::RecognitionCache>,
+    /// Execution provider the models run on ("CUDA", or "CPU" when CUDA is unavailable)
+    execution_provider: &'static str,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "face_api=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let cli = cli::Cli::parse();
+
+    // Printed before logging starts so the output can be redirected into a file as is
+    if let Some(cli::Command::Config { format, .. }) = &cli.command {
+        print!("{}", config::Configuration::example(*format)?);
+        return Ok(());
+    }
 
     // --- Load Configuration ---
-    tracing::info!("Loading configuration...");
-    let config = config::Configuration::load()?;
+    // Loaded before logging starts, since it configures the logging
+    let (config, load_report) = config::Configuration::load(cli.config.as_deref())?;
+    init_logging(&config.logging)?;
+    match &cli.config {
+        Some(path) => tracing::info!("Loaded configuration from {}", path.display()),
+        None => tracing::info!("Loaded configuration"),
+    }
+    load_report.log();
+    config.validate()?;
     tracing::info!("Configuration loaded successfully.");
 
     // --- Load the Font ---
@@ -47,17 +81,73 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Font loaded successfully.");
 
     // --- Load Models ---
-    ort::init()
-        .with_execution_providers([CUDAExecutionProvider::default().build()])
-        .commit()?;
-    
+    let execution_provider = init_execution_providers()?;
+    let shared_state = build_state(&config, font, execution_provider).await?;
+
+    if let Some(cli::Command::Import { dir, force, group }) = &cli.command {
+        return import::run(&shared_state, dir, *force, group.clone()).await;
+    }
+
+    // --- Run Server ---
+    let app = handlers::create_router(shared_state);
+    let server_address = config.server_address();
+    let listener = tokio::net::TcpListener::bind(&server_address).await?;
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    match &config.tls {
+        Some(tls) => {
+            let rustls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await?;
+            tracing::info!("Server listening on https://{}", listener.local_addr()?);
+            axum_server::from_tcp_rustls(listener.into_std()?, rustls_config)
+                .serve(make_service)
+                .await?;
+        }
+        None => {
+            tracing::info!("Server listening on http://{}", listener.local_addr()?);
+            axum::serve(listener, make_service).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Debug logs of this crate and the HTTP layer, when neither `RUST_LOG` nor `logging.filter` is set
+const DEFAULT_LOG_FILTER: &str = concat!(env!("CARGO_CRATE_NAME"), "=debug,tower_http=debug");
+
+/// Install the global tracing subscriber. `RUST_LOG` takes precedence over `logging.filter`.
+fn init_logging(logging: &config::LoggingConfig) -> anyhow::Result<()> {
+    let filter = match tracing_subscriber::EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => {
+            let directives = logging.filter.as_deref().unwrap_or(DEFAULT_LOG_FILTER);
+            tracing_subscriber::EnvFilter::try_new(directives)
+                .map_err(|e| anyhow::anyhow!("Invalid logging.filter {:?}: {}", directives, e))?
+        }
+    };
+
+    let registry = tracing_subscriber::registry().with(filter);
+    match logging.format {
+        config::LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        config::LogFormat::Json => registry.with(tracing_subscriber::fmt::layer().json()).init(),
+    }
+    Ok(())
+}
+
+/// Load the models and open the gallery store described by `config`, and build the
+/// state shared by all requests. The gallery is counted before this returns.
+async fn build_state(config: &config::Configuration, font: FontArc, execution_provider: &'static str) -> anyhow::Result<Arc<AppState>> {
     tracing::info!("Loading models...");
-    tracing::info!("Loading detector from: {:?}", config.models.detector.path);
-    let mut detector_session = SessionBuilder::new()?
-        .commit_from_file(&config.models.detector.path)?;
-    tracing::info!("Loading recognizer from: {:?}", config.models.recognizer.path);
-    let recognizer_session = SessionBuilder::new()?
-        .commit_from_file(&config.models.recognizer.path)?;
+    tracing::info!("Loading detector from: {:?} (device {})", config.models.detector.path, config.models.detector.device_id);
+    let detector_session = build_session(
+        &config.models.detector.path,
+        config.models.detector.device_id,
+        &config.models.detector.session,
+    )?;
+    tracing::info!("Loading recognizer from: {:?} (device {})", config.models.recognizer.path, config.models.recognizer.device_id);
+    let recognizer_session = build_session(
+        &config.models.recognizer.path,
+        config.models.recognizer.device_id,
+        &config.models.recognizer.session,
+    )?;
     tracing::info!("Models loaded successfully.");
 
     // --- Extract Model Metadata ---
@@ -66,50 +156,157 @@ async fn main() -> anyhow::Result<()> {
     let recognizer_metadata = extract_recognizer_metadata(&recognizer_session, &config.models.recognizer)?;
 
     // --- Pre-compute Output Mappings ---
-    tracing::info!("Pre-computing detector output mappings...");
-    let stride_output_mapping = pipeline::match_outputs_by_shape_at_startup(
-        &mut detector_session,
-        &basic_detector_metadata.output_names,
-        &config.models.detector.strides,
-        config.models.detector.input_shape[0],
-        config.models.detector.input_shape[1],
-    )?;
+    let stride_output_mapping = match config.models.detector.resolve_output_mapping(&basic_detector_metadata.output_names)? {
+        Some(mapping) => {
+            tracing::info!("Using manual detector output mapping from configuration");
+            mapping
+        }
+        None => {
+            tracing::info!("Pre-computing detector output mappings...");
+            pipeline::match_outputs_by_shape_at_startup(
+                &detector_session,
+                &basic_detector_metadata,
+                &config.models.detector.strides,
+                config.models.detector.input_shape[0],
+                config.models.detector.input_shape[1],
+                config.models.detector.num_anchors,
+            )?
+        }
+    };
 
-    let detector_metadata = create_detector_metadata_with_mappings(basic_detector_metadata, stride_output_mapping);
+    let detector_metadata = create_detector_metadata_with_mappings(
+        basic_detector_metadata,
+        stride_output_mapping,
+        &config.models.detector,
+    )?;
 
     // Check if we have the expected number of outputs for the strides
-    let expected_outputs = config.models.detector.strides.len() * 3; // 3 outputs per stride
+    // (score, bbox and, unless the model has none, keypoints per stride, or one set
+    // shared by all strides for combined exports)
+    let expected_outputs = detector_metadata
+        .stride_output_mapping
+        .values()
+        .flat_map(|&(score, bbox, kps)| [Some(score), Some(bbox), kps])
+        .flatten()
+        .collect::<std::collections::BTreeSet<usize>>()
+        .len();
     if detector_metadata.output_names.len() != expected_outputs {
         tracing::warn!("Expected {} outputs for {} strides, but got {}. This may cause issues.",
                       expected_outputs, config.models.detector.strides.len(), detector_metadata.output_names.len());
     }
 
-    // --- Connect to SurrealDB ---
-    let db = Surreal::new::<Ws>(config.database_url()).await?;
-    db.signin(Root {
-        username: &config.database.username,
-        password: &config.database.password,
-    })
-    .await?;
-    db.use_ns(&config.database.namespace).use_db(&config.database.database).await?;
-    tracing::info!("Database connection established.");
+    // --- Warm Up Models ---
+    if config.models.warmup_iterations > 0 {
+        tracing::info!("Warming up models ({} iterations)...", config.models.warmup_iterations);
+        let warmup_time = pipeline::warm_up(
+            &detector_session,
+            &recognizer_session,
+            &detector_metadata,
+            &recognizer_metadata,
+            config.models.warmup_iterations,
+        )?;
+        tracing::info!("Models warmed up in {} ms.", warmup_time.as_millis());
+    }
+
+    // --- Open the Gallery Store ---
+    let store: Box<dyn store::GalleryStore> = match config.storage.backend {
+        StorageBackend::Surreal => {
+            let db = db::Database::connect(&config.database).await?;
+            tracing::info!("Database connection established.");
+            Box::new(db)
+        }
+        StorageBackend::Sqlite => {
+            let sqlite = store::SqliteStore::open(&config.storage.sqlite_path)?;
+            tracing::info!("Using SQLite gallery at {:?}", config.storage.sqlite_path);
+            Box::new(sqlite)
+        }
+        StorageBackend::Memory => {
+            tracing::warn!("Using the in-memory gallery store; enrollments are lost on restart");
+            Box::new(store::MemoryStore::new())
+        }
+    };
+
+    let audit = if config.audit.enabled {
+        Some(audit::AuditLog::open(&config.audit).await?)
+    } else {
+        None
+    };
 
     // --- Create Application State ---
     let shared_state = Arc::new(AppState {
-        db,
-        detector_session: Mutex::new(detector_session),
-        recognizer_session: Mutex::new(recognizer_session),
+        store,
+        detector_session: session::SharedSession::new(detector_session, config.models.concurrent_inference),
+        recognizer_session: session::SharedSession::new(recognizer_session, config.models.concurrent_inference),
         font,
         detector_metadata,
         recognizer_metadata,
+        config: config.clone(),
+        gallery_size: AtomicUsize::new(0),
+        incompatible_embeddings: AtomicUsize::new(0),
+        rate_limiter: config.rate_limit.enabled.then(|| rate_limit::RateLimiter::new(&config.rate_limit)),
+        inference_slots: config.models.max_concurrent_inferences.map(|slots| Arc::new(tokio::sync::Semaphore::new(slots))),
+        audit,
+        trackers: Mutex::new(HashMap::new()),
+        cache: config.cache.enabled.then(|| cache::RecognitionCache::new(&config.cache)),
+        execution_provider,
     });
+    let gallery = handlers::reload_gallery(&shared_state).await?;
+    tracing::info!("Gallery loaded with {} enrolled persons", gallery.persons);
+    if config.storage.prewarm && gallery.persons > 0 {
+        // One throwaway lookup pays the first full scan (and fills the database's caches)
+        // now instead of on the first recognition request
+        let prewarm_start = std::time::Instant::now();
+        let probe = vec![0.0; shared_state.recognizer_metadata.embedding_dim];
+        match shared_state.store.query_nearest(&probe, 1, None).await {
+            Ok(_) => tracing::info!("Gallery query path pre-warmed in {} ms", prewarm_start.elapsed().as_millis()),
+            Err(e) => tracing::warn!("Failed to pre-warm the gallery query path: {}", e),
+        }
+    }
+    if gallery.incompatible > 0 {
+        tracing::warn!(
+            "{} of {} enrolled embeddings don't match the recognizer's {}-dimensional output; recognition is disabled until they are re-embedded (POST /admin/reembed) or re-enrolled",
+            gallery.incompatible, gallery.persons, shared_state.recognizer_metadata.embedding_dim
+        );
+    }
+    Ok(shared_state)
+}
 
-    // --- Run Server ---
-    let app = handlers::create_router().with_state(shared_state);
-    let server_address = config.server_address();
-    let listener = tokio::net::TcpListener::bind(&server_address).await?;
-    tracing::info!("Server listening on {}", listener.local_addr()?);
-    axum::serve(listener, app.into_make_service()).await?;
+/// Initialize ONNX Runtime with CUDA as the preferred execution provider and return the
+/// provider the models will run on
+#[cfg(feature = "cuda")]
+fn init_execution_providers() -> anyhow::Result<&'static str> {
+    ort::init()
+        .with_execution_providers([CUDAExecutionProvider::default().build()])
+        .commit()?;
+    if CUDAExecutionProvider::default().is_available().unwrap_or(false) {
+        Ok("CUDA")
+    } else {
+        tracing::warn!("CUDA execution provider is unavailable, falling back to CPU");
+        Ok("CPU")
+    }
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// Initialize ONNX Runtime for a build without the `cuda` feature, which always runs on the CPU
+#[cfg(not(feature = "cuda"))]
+fn init_execution_providers() -> anyhow::Result<&'static str> {
+    ort::init().commit()?;
+    tracing::info!("Built without the cuda feature, running on CPU");
+    Ok("CPU")
+}
+
+/// Build an inference session on the given CUDA device with the configured tuning options.
+/// Without the `cuda` feature the device is ignored and the session runs on the CPU.
+#[cfg_attr(not(feature = "cuda"), allow(unused_variables))]
+fn build_session(path: &Path, device_id: i32, options: &SessionOptions) -> anyhow::Result<Session> {
+    let builder = SessionBuilder::new()?;
+    #[cfg(feature = "cuda")]
+    let builder = builder.with_execution_providers([CUDAExecutionProvider::default().with_device_id(device_id).build()])?;
+    let mut builder = builder.with_optimization_level(options.graph_optimization_level.ort_level())?;
+    if let Some(threads) = options.intra_threads {
+        builder = builder.with_intra_threads(threads)?;
+    }
+    if let Some(threads) = options.inter_threads {
+        builder = builder.with_parallel_execution(true)?.with_inter_threads(threads)?;
+    }
+    Ok(builder.commit_from_file(path)?)
+}
@@ -1,11 +1,22 @@
+use crate::pipeline::estimate_pose;
 use serde::{Deserialize, Serialize};
 
 /// Represents a person's record in the database.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Person {
     pub name: String,
     pub embedding: Vec<f32>,
     pub cropped_image: Vec<u8>, // JPEG encoded cropped face image
+    /// Isolated gallery the person belongs to; `None` is the default gallery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Enrollment time in milliseconds since the Unix epoch; unknown for persons
+    /// enrolled before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Time of the last rename or re-embedding, as `created_at`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
 }
 
 /// Represents a person for gallery display (without embedding data)
@@ -13,6 +24,181 @@ pub struct Person {
 pub struct GalleryPerson {
     pub name: String,
     pub image_base64: String, // Base64 encoded JPEG image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+}
+
+/// A person's identity as returned by the CRUD endpoints (no embedding or image data).
+#[derive(Debug, Serialize)]
+pub struct PersonSummary {
+    /// Record id, e.g. "person:abc123"
+    pub id: String,
+    pub name: String,
+}
+
+/// One person's metadata as returned by `GET /persons/{id}` (no embedding or image data)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PersonDetails {
+    pub id: String,
+    pub name: String,
+    pub embedding_dim: usize,
+    /// Milliseconds since the Unix epoch, `null` for persons enrolled before it was recorded
+    pub created_at: Option<u64>,
+    /// Time of the last rename or re-embedding, as `created_at`
+    pub updated_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+/// Body of `PATCH /persons/{id}`
+#[derive(Debug, Deserialize)]
+pub struct RenamePerson {
+    pub name: String,
+}
+
+/// A person's record without its embedding: the gallery crop is enough to display
+/// the person or to recompute the embedding.
+#[derive(Debug)]
+pub struct StoredCrop {
+    pub id: String,
+    pub name: String,
+    pub cropped_image: Vec<u8>,
+    pub group: Option<String>,
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+}
+
+/// Outcome of re-embedding the whole gallery with the current recognizer.
+#[derive(Debug, Serialize)]
+pub struct ReembedReport {
+    pub total: usize,
+    pub updated: usize,
+    pub failed: Vec<ReembedFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReembedFailure {
+    pub id: String,
+    pub name: String,
+    pub error: String,
+}
+
+/// One line of a gallery export, as written by `/admin/export` and read by `/admin/import`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedPerson {
+    pub name: String,
+    pub embedding: Vec<f32>,
+    /// Base64 encoded JPEG gallery crop; omitted with `?crops=false`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cropped_image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    /// Include the gallery crops, e.g. /admin/export?crops=false for embeddings only
+    #[serde(default = "default_export_crops")]
+    pub crops: bool,
+}
+
+fn default_export_crops() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub total: usize,
+    pub imported: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportFailure {
+    /// 1-based line number in the uploaded export
+    pub line: usize,
+    pub error: String,
+}
+
+/// A person's embedding with just enough metadata to report it, for comparing the
+/// enrolled persons with each other
+#[derive(Debug)]
+pub struct StoredEmbedding {
+    pub id: String,
+    pub name: String,
+    pub group: Option<String>,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SimilarityParams {
+    /// Only report pairs at least this similar; defaults to `recognition.duplicate_threshold`
+    pub threshold: Option<f32>,
+    /// Number of pairs to skip, for paging through the result
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_similarity_limit")]
+    pub limit: usize,
+}
+
+fn default_similarity_limit() -> usize {
+    100
+}
+
+/// Pairs of enrolled persons of the same group whose embeddings are suspiciously similar,
+/// as returned by `/admin/similarity-matrix`
+#[derive(Debug, Serialize)]
+pub struct SimilarityMatrix {
+    pub threshold: f32,
+    /// Number of pairs at or above the threshold, of which `pairs` is one page
+    pub total: usize,
+    pub offset: usize,
+    pub pairs: Vec<SimilarPair>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimilarPair {
+    pub a: PersonSummary,
+    pub b: PersonSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub similarity: f32,
+}
+
+/// Summary of the gallery as seen by the server.
+#[derive(Debug, Serialize)]
+pub struct GalleryStatus {
+    pub persons: usize,
+    /// Records whose embedding dimension doesn't match the current recognizer
+    pub incompatible: usize,
+}
+
+/// Server summary for monitoring dashboards.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub gallery: GalleryStatus,
+    pub embedding_dim: usize,
+    /// Whether stored and returned embeddings are L2-normalized
+    pub embeddings_normalized: bool,
+    pub execution_provider: &'static str,
+    /// CUDA was requested but unavailable, so the models run on the CPU
+    pub cuda_fallback: bool,
+    pub detector: ModelStats,
+    pub recognizer: ModelStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelStats {
+    pub path: String,
+    pub input_shape: Vec<i64>,
+    /// CUDA device the model is bound to, `None` when running on the CPU
+    pub device_id: Option<i32>,
 }
 
 /// Represents a clean, decoded face detection.
@@ -32,11 +218,37 @@ impl DetectedFace {
         self.bbox[1] = (self.bbox[1] * scale_h) - y_offset; // y1
         self.bbox[3] = (self.bbox[3] * scale_h) - y_offset; // y2
 
-        // Scale keypoints
-        self.kps.iter_mut().for_each(|point| {
-            point[0] = (point[0] * scale_w) - x_offset; // x
-            point[1] = (point[1] * scale_h) - y_offset; // y
-        });
+        // Scale keypoints, leaving absent (all-zero) keypoints untouched
+        if self.has_keypoints() {
+            self.kps.iter_mut().for_each(|point| {
+                point[0] = (point[0] * scale_w) - x_offset; // x
+                point[1] = (point[1] * scale_h) - y_offset; // y
+            });
+        }
+    }
+
+    /// Whether landmarks are present. Keypoint-less detectors and bbox enrollment
+    /// leave every keypoint at zero.
+    pub fn has_keypoints(&self) -> bool {
+        self.kps.iter().flatten().any(|&v| v != 0.0)
+    }
+
+    /// The keypoints, or `None` when the face has no landmarks
+    pub fn keypoints(&self) -> Option<[[f32; 2]; 5]> {
+        self.has_keypoints().then_some(self.kps)
+    }
+
+    /// The bounding box clamped to the image and rounded to whole pixels, for responses.
+    /// Detections near the border can extend past it after `scale_to_original`; the
+    /// unclamped box is kept on the face for cropping and alignment.
+    pub fn clamp_to_image(&self, image_width: u32, image_height: u32) -> [f32; 4] {
+        let (width, height) = (image_width as f32, image_height as f32);
+        [
+            self.bbox[0].clamp(0.0, width).round(),
+            self.bbox[1].clamp(0.0, height).round(),
+            self.bbox[2].clamp(0.0, width).round(),
+            self.bbox[3].clamp(0.0, height).round(),
+        ]
     }
 
     /// Validate that bounding box coordinates are within image bounds
@@ -49,12 +261,16 @@ impl DetectedFace {
             && self.bbox[1] < self.bbox[3]
     }
 
-    /// Get safe crop coordinates, ensuring they're within bounds
-    pub fn get_safe_crop_coords(&self, image_width: u32, image_height: u32) -> (u32, u32, u32, u32) {
-        let x1 = self.bbox[0].max(0.0).round() as u32;
-        let y1 = self.bbox[1].max(0.0).round() as u32;
-        let x2 = self.bbox[2].min(image_width as f32).round() as u32;
-        let y2 = self.bbox[3].min(image_height as f32).round() as u32;
+    /// Get safe crop coordinates, ensuring they're within bounds.
+    /// `padding` grows the box by that fraction of its width and height (split evenly
+    /// between both sides) before clamping; 0 crops the bounding box exactly.
+    pub fn get_safe_crop_coords(&self, image_width: u32, image_height: u32, padding: f32) -> (u32, u32, u32, u32) {
+        let pad_x = (self.bbox[2] - self.bbox[0]) * padding / 2.0;
+        let pad_y = (self.bbox[3] - self.bbox[1]) * padding / 2.0;
+        let x1 = (self.bbox[0] - pad_x).max(0.0).round() as u32;
+        let y1 = (self.bbox[1] - pad_y).max(0.0).round() as u32;
+        let x2 = (self.bbox[2] + pad_x).min(image_width as f32).round() as u32;
+        let y2 = (self.bbox[3] + pad_y).min(image_height as f32).round() as u32;
 
         let width = x2.saturating_sub(x1).max(1);
         let height = y2.saturating_sub(y1).max(1);
@@ -89,21 +305,139 @@ impl DetectedFace {
 }
 
 /// Represents the final result for a recognized face.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecognitionResult {
     pub name: String,
     pub similarity: f32,
+    /// Whether the face is accepted as this person. Unset for raw gallery lookups.
+    #[serde(default)]
+    pub is_match: bool,
     #[serde(skip_serializing_if = "Option::is_none")] 
     pub bbox: Option<[f32; 4]>,
+    /// The 5 facial landmarks as [x, y], omitted when the detector provides none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keypoints: Option<[[f32; 2]; 5]>,
+    /// The face's track with `/recognize?track=<stream>`, stable across the stream's frames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_id: Option<u64>,
+}
+
+/// `/recognize?annotate=true` response: the results plus the image with them drawn on it
+#[derive(Debug, Serialize)]
+pub struct AnnotatedRecognition {
+    pub results: Vec<RecognitionResult>,
+    /// Base64 encoded JPEG
+    pub annotated_image: String,
+}
+
+/// A detected face without recognition, as returned by `/detect`.
+#[derive(Debug, Serialize)]
+pub struct Detection {
+    pub bbox: [f32; 4],
+    pub score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keypoints: Option<[[f32; 2]; 5]>,
+    /// Head pose estimated from the keypoints, omitted when there are none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pose: Option<Pose>,
+}
+
+impl From<&DetectedFace> for Detection {
+    fn from(face: &DetectedFace) -> Self {
+        Detection { bbox: face.bbox, score: face.score, keypoints: face.keypoints(), pose: estimate_pose(face) }
+    }
+}
+
+/// Rough head pose from the 5 facial landmarks, in degrees (see [`estimate_pose`])
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Pose {
+    /// Left-right turn, positive when the nose points to the right of the image
+    pub yaw: f32,
+    /// Up-down tilt, positive when looking down
+    pub pitch: f32,
+    /// In-plane rotation of the eye line, positive when clockwise in the image
+    pub roll: f32,
+    /// 1 for a face looking straight at the camera, falling towards 0 as it turns away
+    pub frontalness: f32,
 }
 
 pub struct FinalResult {
     pub detection: DetectedFace,
-    pub recognition: Option<(String, f32)>, // (Name, Similarity Score)
+    pub recognition: Option<(String, f32)>, // (Name, Similarity Score) of the best candidate
+    /// Whether the best candidate is accepted as a match
+    pub is_match: bool,
 }
 
+/// A face found by `/cluster`, tagged with the image it came from and its cluster.
+#[derive(Debug, Serialize)]
+pub struct ClusteredFace {
+    /// Index of the source image in upload order
+    pub image: usize,
+    pub bbox: [f32; 4],
+    pub cluster: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClusterResponse {
+    pub clusters: usize,
+    pub faces: Vec<ClusteredFace>,
+}
+
+/// JSON alternative to the multipart form of `/enroll`, `/enroll-from-bbox` and `/recognize`
 #[derive(Debug, Deserialize)]
+pub struct ImageUploadBody {
+    /// The image, base64 encoded; a `data:` URL prefix is accepted
+    pub image_base64: String,
+    /// Required by the enrollment endpoints
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Face box as `[x1, y1, x2, y2]`, required by `/enroll-from-bbox`
+    #[serde(default)]
+    pub bbox: Option<[f32; 4]>,
+}
+
+/// Query parameters accepted by the enrollment endpoints
+#[derive(Debug, Default, Deserialize)]
+pub struct EnrollParams {
+    /// Detection threshold override, as for `DebugParams`
+    pub threshold: Option<f32>,
+    /// Enroll even if the face closely matches an existing person
+    #[serde(default)]
+    pub force: bool,
+    /// Gallery to enroll into, e.g. /enroll?group=acme; omitted for the default gallery
+    pub group: Option<String>,
+}
+
+impl EnrollParams {
+    pub fn detection_params(&self) -> DebugParams {
+        DebugParams { threshold: self.threshold, ..Default::default() }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct DebugParams {
     // You can call /debug/detector?threshold=0.6
     pub threshold: Option<f32>,
+    // Overrides recognition.recognize_min_score, e.g. /recognize?recognize_min_score=0.8
+    pub recognize_min_score: Option<f32>,
+    // Stages run by /debug/detector, e.g. ?stages=detect,draw
+    pub stages: Option<String>,
+    // Also return the annotated image from /recognize, e.g. /recognize?annotate=true
+    #[serde(default)]
+    pub annotate: bool,
+    // Gallery to match against, e.g. /recognize?group=acme; omitted for the default gallery
+    pub group: Option<String>,
+    // Overlay the feature-map grid of one stride on /debug/detector, e.g. ?grid=16
+    pub grid: Option<i32>,
+    // Label each keypoint with its index on /debug/detector, e.g. ?keypoints=true
+    #[serde(default)]
+    pub keypoints: bool,
+    // Follow faces across the frames of a video stream, e.g. /recognize?track=camera-1
+    pub track: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GalleryParams {
+    /// Only list the persons of one gallery, e.g. /gallery?group=acme
+    pub group: Option<String>,
 }
\ No newline at end of file
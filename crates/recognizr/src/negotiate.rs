@@ -0,0 +1,135 @@
+use crate::pipeline::render_message_image;
+use crate::AppState;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+/// Error bodies are small; anything larger is passed through untouched
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+/// Representations an error response can be rewritten to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    Json,
+    Text,
+    Image,
+}
+
+impl ErrorFormat {
+    fn media_type(self) -> &'static str {
+        match self {
+            ErrorFormat::Json => "application/json",
+            ErrorFormat::Text => "text/plain",
+            ErrorFormat::Image => "image/png",
+        }
+    }
+}
+
+/// Middleware rewriting JSON error bodies for clients that don't ask for JSON.
+///
+/// Errors stay JSON unless the `Accept` header prefers another format: plain text
+/// (`CODE: message`) for clients asking for `text/plain`, and on the `/debug` endpoints,
+/// which return images, a PNG with the message drawn on it, so an `<img>` pointing at
+/// them shows what went wrong. The status code and headers are kept either way.
+pub async fn negotiate_errors(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let offered: &[ErrorFormat] = if request.uri().path().starts_with("/debug/") {
+        &[ErrorFormat::Json, ErrorFormat::Image, ErrorFormat::Text]
+    } else {
+        &[ErrorFormat::Json, ErrorFormat::Text]
+    };
+    let accept = request.headers().get(header::ACCEPT).and_then(|value| value.to_str().ok());
+    let format = preferred_format(accept, offered);
+
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    let is_error = response.status().is_client_error() || response.status().is_server_error();
+    if format == ErrorFormat::Json || !is_error || (format == ErrorFormat::Text && !is_json) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let message = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|body| Some(format!("{}: {}", body["code"].as_str()?, body["error"].as_str()?)))
+        .unwrap_or_else(|| String::from_utf8_lossy(&bytes).trim().to_string());
+
+    // Falls back to plain text if the image can't be encoded
+    let png = (format == ErrorFormat::Image)
+        .then(|| {
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            render_message_image(&message, &state.font)
+                .write_to(&mut buffer, image::ImageFormat::Png)
+                .map(|()| buffer.into_inner())
+                .inspect_err(|e| tracing::warn!("Failed to render error image: {}", e))
+                .ok()
+        })
+        .flatten();
+    let (content_type, body) = match png {
+        Some(png) => (ErrorFormat::Image.media_type(), Body::from(png)),
+        None => ("text/plain; charset=utf-8", Body::from(message + "\n")),
+    };
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body)
+}
+
+/// The offered format the `Accept` header ranks highest, judged by the most specific
+/// matching media range and ties going to the earlier format. JSON is kept when the
+/// header is missing or accepts none of the offered formats.
+fn preferred_format(accept: Option<&str>, offered: &[ErrorFormat]) -> ErrorFormat {
+    let Some(accept) = accept else {
+        return ErrorFormat::Json;
+    };
+    let ranges: Vec<(&str, f32)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let media_range = params.next()?.trim();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            Some((media_range, quality))
+        })
+        .collect();
+
+    let quality = |media_type: &str| {
+        let kind = media_type.split('/').next().unwrap_or_default();
+        ranges
+            .iter()
+            .filter_map(|&(range, quality)| {
+                let specificity = if range.eq_ignore_ascii_case(media_type) {
+                    2
+                } else if range.strip_suffix("/*").is_some_and(|range_kind| range_kind.eq_ignore_ascii_case(kind)) {
+                    1
+                } else if range == "*/*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, quality))
+            })
+            .max_by_key(|&(specificity, _)| specificity)
+            .map_or(0.0, |(_, quality)| quality)
+    };
+
+    let mut best = (ErrorFormat::Json, 0.0);
+    for &format in offered {
+        let quality = quality(format.media_type());
+        if quality > best.1 {
+            best = (format, quality);
+        }
+    }
+    best.0
+}
@@ -1,12 +1,17 @@
 use crate::error::AppError;
-use crate::models::{DebugParams, DetectedFace, FinalResult};
-use crate::config::ModelMetadata;
-use image::{imageops, DynamicImage, GenericImageView, RgbImage, Rgba};
-use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_text_mut};
+use crate::models::{DebugParams, DetectedFace, FinalResult, Pose};
+use crate::config::{ColorMode, DrawingConfig, ImageConfig, ModelMetadata, NormalizationConfig, TrackingConfig};
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{imageops, AnimationDecoder, DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader, RgbImage, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_filled_rect_mut, draw_hollow_rect_mut, draw_line_segment_mut, draw_text_mut, text_size};
 use imageproc::rect::Rect;
-use ndarray::{s, Array, ArrayBase, Dim, IxDynImpl, ViewRepr};
+use ndarray::{concatenate, s, Array, Array4, ArrayBase, Axis, Dim, IxDynImpl, ViewRepr};
 use ort::{inputs, session::{Session}, value::Value};
 use ab_glyph::{FontArc, PxScale};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 use tracing::debug;
 
 // --- TUNING PARAMETERS ---
@@ -19,8 +24,106 @@ pub const Y_OFFSET: f32 = 50.0;
 
 // --- IMAGE PROCESSING CONSTANTS ---
 const LETTERBOX_FILL_COLOR: [u8; 3] = [114, 114, 114]; // Gray color for letterbox padding
-const NORMALIZATION_MEAN: f32 = 127.5;
-const NORMALIZATION_SCALE: f32 = 127.5;
+
+/// Decodes an uploaded image, applies its EXIF orientation and converts it to 8-bit RGB.
+///
+/// Phone photos are commonly stored sideways with an orientation tag, which
+/// `image::load_from_memory` ignores. Every handler decodes through here so the
+/// detector and recognizer always see the upright image. The format is sniffed from
+/// the magic bytes and checked against the configured allowlist, and the pixel count
+/// is checked from the header, all before any pixel data is decoded. Animated images
+/// are reduced to their first frame.
+pub fn decode_image(image_bytes: &[u8], config: &ImageConfig) -> Result<DynamicImage, AppError> {
+    let format = image::guess_format(image_bytes)
+        .map_err(|_| AppError::InvalidImage("Unrecognized image format".to_string()))?;
+    if !config.allowed_formats.iter().any(|allowed| allowed.image_format() == format) {
+        return Err(AppError::InvalidImage(format!("Image format {:?} is not allowed", format)));
+    }
+
+    let mut decoder = ImageReader::with_format(std::io::Cursor::new(image_bytes), format).into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    if width as u64 * height as u64 > config.max_pixels {
+        return Err(AppError::ImageTooLarge(format!(
+            "Image is {}x{} pixels, exceeding the limit of {} pixels",
+            width, height, config.max_pixels
+        )));
+    }
+    let orientation = decoder.orientation()?;
+
+    let mut image = match decode_first_frame(image_bytes, format)? {
+        Some(frame) => frame,
+        None => DynamicImage::from_decoder(decoder)?,
+    };
+    image.apply_orientation(orientation);
+    Ok(into_rgb8(image))
+}
+
+/// Decodes the first frame of an animated GIF, APNG or WebP, so animated uploads are
+/// always analyzed on a well-defined frame. Returns `None` for still images.
+fn decode_first_frame(image_bytes: &[u8], format: ImageFormat) -> Result<Option<DynamicImage>, AppError> {
+    let cursor = std::io::Cursor::new(image_bytes);
+    let frames = match format {
+        ImageFormat::Gif => GifDecoder::new(cursor)?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(cursor)?;
+            if !decoder.is_apng()? {
+                return Ok(None);
+            }
+            decoder.apng()?.into_frames()
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(cursor)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok(None),
+    };
+
+    let frame = frames
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::InvalidImage("Animated image has no frames".to_string()))??;
+    debug!("Using the first frame of an animated {:?} image", format);
+    Ok(Some(DynamicImage::ImageRgba8(frame.into_buffer())))
+}
+
+/// Converts any image variant to 8-bit RGB.
+///
+/// Grayscale inputs are replicated across all three channels, 16-bit and float
+/// inputs are rescaled to 0-255 and alpha is dropped, so the normalization
+/// constants and the JPEG encoder always see the pixel format they expect.
+fn into_rgb8(image: DynamicImage) -> DynamicImage {
+    match image {
+        DynamicImage::ImageRgb8(_) => image,
+        other => {
+            debug!("Converting {:?} image to 8-bit RGB", other.color());
+            DynamicImage::ImageRgb8(other.to_rgb8())
+        }
+    }
+}
+
+/// 64-bit difference hash of an image: the brightness gradients of a 9x8 grayscale
+/// thumbnail. Re-encoded or slightly recompressed copies of an image hash the same,
+/// so it identifies repeated uploads where a hash of the bytes wouldn't.
+pub fn perceptual_hash(image: &DynamicImage) -> u64 {
+    let thumbnail = image.resize_exact(9, 8, imageops::FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumbnail.get_pixel(x + 1, y)[0] > thumbnail.get_pixel(x, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    hash
+}
+
+/// SHA-256 of an image's decoded pixels. Unlike [`perceptual_hash`], only images with
+/// exactly the same pixels hash the same.
+pub fn content_hash(image: &DynamicImage) -> [u8; 32] {
+    Sha256::digest(image.as_bytes()).into()
+}
 
 /// Preprocesses an image using the "top-left" letterbox method.
 /// A direct Rust translation of the Python `preprocess_image_topleft` function.
@@ -28,6 +131,7 @@ fn preprocess_image_topleft(
     img: &DynamicImage,
     target_height: u32,
     target_width: u32,
+    filter: imageops::FilterType,
 ) -> (RgbImage, u32, u32) {
     let img_h = img.height();
     let img_w = img.width();
@@ -41,95 +145,232 @@ fn preprocess_image_topleft(
     let new_h = (img_h as f32 * ratio).round() as u32;
 
     let rgb_img = img.to_rgb8();
-    let resized_img = imageops::resize(&rgb_img, new_w, new_h, imageops::FilterType::Triangle);
+    let resized_img = imageops::resize(&rgb_img, new_w, new_h, filter);
     let mut canvas = RgbImage::from_pixel(target_width, target_height, image::Rgb(LETTERBOX_FILL_COLOR));
     imageops::overlay(&mut canvas, &resized_img, 0, 0);
 
     (canvas, new_w, new_h)
 }
 
+/// Builds a normalized `(1, 3, H, W)` input tensor from an RGB image,
+/// reordering channels as configured for the model.
+fn image_to_tensor(image: &RgbImage, normalization: &NormalizationConfig) -> Array4<f32> {
+    let (width, height) = image.dimensions();
+    let channels = normalization.channel_order.source_indices();
+
+    let mut input_tensor = Array::zeros((1, 3, height as usize, width as usize));
+    for (x, y, pixel) in image.enumerate_pixels() {
+        for (c, &source) in channels.iter().enumerate() {
+            input_tensor[[0, c, y as usize, x as usize]] =
+                (pixel[source] as f32 - normalization.mean[c]) / normalization.std[c];
+        }
+    }
+    input_tensor
+}
+
 /// Detects faces in an image using the SCRFD model.
 ///
 /// # Arguments
 /// * `session` - Mutable reference to the ONNX runtime session
-/// * `image_bytes` - Raw image data as bytes
+/// * `image` - Decoded, upright image (see [`decode_image`])
 /// * `params` - Debug parameters for controlling detection behavior
 /// * `detector_metadata` - Pre-computed model metadata with output mappings
 ///
 /// # Returns
-/// * `Ok((faces, width, height))` - List of detected faces and resized image dimensions
+/// * `Ok((faces, width, height))` - List of detected faces (possibly empty) and resized image dimensions
 /// * `Err(AppError)` - If detection fails
 ///
 /// # Performance
-/// Uses pre-computed output mappings for efficient tensor extraction.
+/// Uses pre-computed output mappings for efficient tensor extraction. Images larger
+/// than the configured `max_working_dimension` are first downsized with a fast sampler.
+/// The returned dimensions still describe the whole image within the detector input,
+/// so callers scale back to the original image unchanged.
+/// Each configured pyramid scale adds one detector run per tile of the enlarged image.
 pub fn detect_faces(
-    session: &mut Session,
-    image_bytes: &[u8],
+    session: &Session,
+    image: &DynamicImage,
     params: &DebugParams,
     detector_metadata: &crate::config::DetectorMetadata,
 ) -> Result<(Vec<DetectedFace>, u32, u32), AppError> {
-    let image = image::load_from_memory(image_bytes)?;
-
     // Extract target shape from detector metadata
     let target_height = detector_metadata.input_shape[2] as u32;
     let target_width = detector_metadata.input_shape[3] as u32;
 
+    let working_image = match detector_metadata.max_working_dimension {
+        Some(max) if image.width().max(image.height()) > max => {
+            debug!("Downsizing {}x{} image to at most {}px before detection", image.width(), image.height(), max);
+            std::borrow::Cow::Owned(image.thumbnail(max, max))
+        }
+        _ => std::borrow::Cow::Borrowed(image),
+    };
+
+    let filter = detector_metadata.interpolation.filter_type();
     let (processed_img, new_w, new_h) =
-        preprocess_image_topleft(&image, target_height, target_width);
+        preprocess_image_topleft(&working_image, target_height, target_width, filter);
+    let mut proposals = run_detector(session, &processed_img, params, detector_metadata)?;
+
+    // Image pyramid: extra passes over the enlarged image, tiled into detector-sized
+    // windows. Proposals are mapped back into the single-scale frame, so callers scale
+    // all faces with the same `new_w`/`new_h`, and the combined NMS merges duplicates.
+    if !detector_metadata.pyramid_scales.is_empty() {
+        let rgb_image = working_image.to_rgb8();
+        for &scale in &detector_metadata.pyramid_scales {
+            let scaled_w = (new_w as f32 * scale).round() as u32;
+            let scaled_h = (new_h as f32 * scale).round() as u32;
+            let scaled = imageops::resize(&rgb_image, scaled_w, scaled_h, filter);
+
+            let tiles_x = tile_origins(scaled_w, target_width);
+            let tiles_y = tile_origins(scaled_h, target_height);
+            debug!("Pyramid pass at {}x: {} tiles", scale, tiles_x.len() * tiles_y.len());
+            for &tile_y in &tiles_y {
+                for &tile_x in &tiles_x {
+                    let tile_w = target_width.min(scaled_w - tile_x);
+                    let tile_h = target_height.min(scaled_h - tile_y);
+                    let tile = imageops::crop_imm(&scaled, tile_x, tile_y, tile_w, tile_h).to_image();
+                    let mut canvas = RgbImage::from_pixel(target_width, target_height, image::Rgb(LETTERBOX_FILL_COLOR));
+                    imageops::overlay(&mut canvas, &tile, 0, 0);
+
+                    for mut face in run_detector(session, &canvas, params, detector_metadata)? {
+                        let has_keypoints = face.has_keypoints();
+                        for (i, value) in face.bbox.iter_mut().enumerate() {
+                            let offset = if i % 2 == 0 { tile_x } else { tile_y };
+                            *value = (*value + offset as f32) / scale;
+                        }
+                        if has_keypoints {
+                            for point in face.kps.iter_mut() {
+                                point[0] = (point[0] + tile_x as f32) / scale;
+                                point[1] = (point[1] + tile_y as f32) / scale;
+                            }
+                        }
+                        proposals.push(face);
+                    }
+                }
+            }
+        }
+    }
 
-    let mut input_tensor = Array::zeros((1, 3, target_height as usize, target_width as usize));
-    for (x, y, pixel) in processed_img.enumerate_pixels() {
-        // Normalize pixel values: (pixel - mean) / scale, using BGR order
-        input_tensor[[0, 0, y as usize, x as usize]] = (pixel[2] as f32 - NORMALIZATION_MEAN) / NORMALIZATION_SCALE;
-        input_tensor[[0, 1, y as usize, x as usize]] = (pixel[1] as f32 - NORMALIZATION_MEAN) / NORMALIZATION_SCALE;
-        input_tensor[[0, 2, y as usize, x as usize]] = (pixel[0] as f32 - NORMALIZATION_MEAN) / NORMALIZATION_SCALE;
+    // No proposals above the threshold is a normal outcome, not an error:
+    // callers decide what an empty result means for their endpoint.
+    if proposals.is_empty() {
+        debug!("Detector produced no proposals above the confidence threshold");
+        return Ok((Vec::new(), new_w, new_h));
     }
 
+    let final_faces = non_maximum_suppression(&proposals, NMS_THRESHOLD);
+
+    Ok((final_faces, new_w, new_h))
+}
+
+/// Runs the detector on one detector-sized image and decodes its proposals, before NMS
+fn run_detector(
+    session: &Session,
+    input_image: &RgbImage,
+    params: &DebugParams,
+    detector_metadata: &crate::config::DetectorMetadata,
+) -> Result<Vec<DetectedFace>, AppError> {
+    let (target_width, target_height) = input_image.dimensions();
+    let input_tensor = detector_metadata
+        .input_layout
+        .arrange(image_to_tensor(input_image, &detector_metadata.normalization));
+
     let inputs = inputs![&detector_metadata.input_name => Value::from_array(input_tensor)?]?;
     let outputs = session.run(inputs)?;
     
     // Use pre-computed output mappings to extract tensors efficiently
     let mut all_outputs = Vec::new();
 
-    for (&stride, &(score_idx, bbox_idx, kps_idx)) in &detector_metadata.stride_output_mapping {
-        let score_name = &detector_metadata.output_names[score_idx];
-        let bbox_name = &detector_metadata.output_names[bbox_idx];
-        let kps_name = &detector_metadata.output_names[kps_idx];
+    // Combined exports concatenate the rows of every stride in ascending stride order
+    let mut strides: Vec<i32> = detector_metadata.stride_output_mapping.keys().copied().collect();
+    strides.sort_unstable();
+    let mut row_offset = 0;
+
+    for stride in strides {
+        let (score_idx, bbox_idx, kps_idx) = detector_metadata.stride_output_mapping[&stride];
+        let rows = detector_metadata.combined_outputs.then(|| {
+            let (feature_width, feature_height) = feature_map_size(target_width as f32, target_height as f32, stride);
+            let start = row_offset;
+            row_offset += feature_width * feature_height * detector_metadata.num_anchors;
+            start..row_offset
+        });
+        let extract = |idx: usize| -> Result<_, AppError> {
+            let name = &detector_metadata.output_names[idx];
+            let tensor = outputs[name.as_str()].try_extract_tensor::<f32>()?;
+            match &rows {
+                Some(rows) => stride_rows(tensor, name, rows.clone()),
+                None => Ok(tensor),
+            }
+        };
 
-        let score = outputs[score_name.as_str()].try_extract_tensor::<f32>()?;
-        let bbox = outputs[bbox_name.as_str()].try_extract_tensor::<f32>()?;
-        let kps = outputs[kps_name.as_str()].try_extract_tensor::<f32>()?;
+        let score = extract(score_idx)?;
+        let bbox = extract(bbox_idx)?;
+        let kps = kps_idx.map(extract).transpose()?;
 
         all_outputs.push((stride, score, bbox, kps));
     }
 
-    let proposals = decode_proposals(&all_outputs, target_width as f32, target_height as f32, params)?;
+    decode_proposals(
+        &all_outputs,
+        target_width as f32,
+        target_height as f32,
+        params,
+        detector_metadata,
+    )
+}
 
-    let final_faces = non_maximum_suppression(&proposals, NMS_THRESHOLD);
+/// The `rows` of one stride in a combined detector output of shape `[N, C]` or `[1, N, C]`
+fn stride_rows<'a>(
+    tensor: ArrayBase<ViewRepr<&'a f32>, Dim<IxDynImpl>>,
+    name: &str,
+    rows: Range<usize>,
+) -> Result<ArrayBase<ViewRepr<&'a f32>, Dim<IxDynImpl>>, AppError> {
+    let tensor = if tensor.ndim() == 3 && tensor.shape()[0] == 1 { tensor.index_axis_move(Axis(0), 0) } else { tensor };
+    if tensor.ndim() != 2 || tensor.shape()[0] < rows.end {
+        return Err(AppError::Internal(anyhow::anyhow!(
+            "Combined detector output {} has shape {:?}, expected at least {} rows",
+            name,
+            tensor.shape(),
+            rows.end
+        )));
+    }
+    Ok(tensor.slice_move(s![rows, ..]).into_dyn())
+}
 
-    Ok((final_faces, new_w, new_h))
+/// Start offsets of windows of `window` pixels covering `length` pixels, overlapping
+/// by a quarter window so faces on a tile border are whole in at least one tile
+fn tile_origins(length: u32, window: u32) -> Vec<u32> {
+    if length <= window {
+        return vec![0];
+    }
+    let step = (window * 3 / 4).max(1);
+    let mut origins: Vec<u32> = (0..length - window).step_by(step as usize).collect();
+    origins.push(length - window);
+    origins
 }
 
 /// Decodes raw model output into candidate faces.
 fn decode_proposals(
-    outputs: &[(i32, ArrayBase<ViewRepr<&f32>, Dim<IxDynImpl>>, ArrayBase<ViewRepr<&f32>, Dim<IxDynImpl>>, ArrayBase<ViewRepr<&f32>, Dim<IxDynImpl>>)],
+    outputs: &[(i32, ArrayBase<ViewRepr<&f32>, Dim<IxDynImpl>>, ArrayBase<ViewRepr<&f32>, Dim<IxDynImpl>>, Option<ArrayBase<ViewRepr<&f32>, Dim<IxDynImpl>>>)],
     img_width: f32,
     img_height: f32,
     params: &DebugParams,
+    detector_metadata: &crate::config::DetectorMetadata,
 ) -> Result<Vec<DetectedFace>, AppError> {
-    let conf_threshold = params.threshold.unwrap_or(0.7);
+    let num_anchors = detector_metadata.num_anchors;
     let mut proposals = Vec::new();
 
     for (stride, scores_tuple, boxes, kps) in outputs {
+        // A request-level threshold overrides everything, then the per-stride config
+        let conf_threshold = params.threshold
+            .or_else(|| detector_metadata.stride_thresholds.get(stride).copied())
+            .unwrap_or(detector_metadata.default_threshold);
         let scores = scores_tuple.slice(s![.., 0]);
 
-        let feature_height = (img_height / *stride as f32).ceil() as usize;
-        let feature_width = (img_width / *stride as f32).ceil() as usize;
+        let (feature_width, feature_height) = feature_map_size(img_width, img_height, *stride);
 
         for y in 0..feature_height {
             for x in 0..feature_width {
-                for anchor_idx in 0..2 {
-                    let idx = y * feature_width * 2 + x * 2 + anchor_idx;
+                for anchor_idx in 0..num_anchors {
+                    let idx = (y * feature_width + x) * num_anchors + anchor_idx;
                     if idx >= scores.len() { continue; }
                     let score = scores[idx];
 
@@ -137,10 +378,7 @@ fn decode_proposals(
 
                     let box_pred_arr = boxes.slice(s![idx as usize, ..]);
                     let box_pred = box_pred_arr.as_slice().unwrap();
-                    let kps_pred_arr = kps.slice(s![idx as usize, ..]);
-                    let kps_pred = kps_pred_arr.as_slice().unwrap();
-                    let anchor_cx = (x as f32 + 0.5) * *stride as f32;
-                    let anchor_cy = (y as f32 + 0.5) * *stride as f32;
+                    let (anchor_cx, anchor_cy) = anchor_center(x, y, *stride);
 
                     let l = box_pred[0] * *stride as f32;
                     let t = box_pred[1] * *stride as f32;
@@ -148,27 +386,25 @@ fn decode_proposals(
                     let b = box_pred[3] * *stride as f32;
                     let bbox = [anchor_cx - l, anchor_cy - t, anchor_cx + r, anchor_cy + b];
 
+                    // Keypoint-less detectors leave every keypoint at zero
                     let mut decoded_kps = [[0.0; 2]; 5];
-                    let expected_kps_len = 10; // 5 keypoints * 2 coordinates each
+                    if let Some(kps) = kps {
+                        let kps_pred_arr = kps.slice(s![idx as usize, ..]);
+                        let kps_pred = kps_pred_arr.as_slice().unwrap();
+                        let expected_kps_len = 10; // 5 keypoints * 2 coordinates each
 
-                    if kps_pred.len() >= expected_kps_len {
-                        for k in 0..5 {
-                            if k * 2 + 1 < kps_pred.len() {
+                        if kps_pred.len() >= expected_kps_len {
+                            for k in 0..5 {
                                 let kps_x = anchor_cx + kps_pred[k * 2] * *stride as f32;
                                 let kps_y = anchor_cy + kps_pred[k * 2 + 1] * *stride as f32;
                                 decoded_kps[k] = [kps_x, kps_y];
-                            } else {
-                                tracing::warn!("Keypoint {} index out of bounds for kps_pred len {}", k, kps_pred.len());
-                                break;
                             }
+                        } else {
+                            tracing::warn!("Insufficient keypoints data for stride {}: expected {}, got {}",
+                                          stride, expected_kps_len, kps_pred.len());
                         }
-                    } else {
-                        // If keypoints data is insufficient, use default values or skip
-                        tracing::warn!("Insufficient keypoints data for stride {}: expected {}, got {}",
-                                      stride, expected_kps_len, kps_pred.len());
-                        // Keep default zeros for keypoints
                     }
-                    
+
                     proposals.push(DetectedFace { bbox, kps: decoded_kps, score });
                 }
             }
@@ -177,6 +413,16 @@ fn decode_proposals(
     Ok(proposals)
 }
 
+/// Width and height of the feature map one stride produces for a detector input of the given size
+fn feature_map_size(img_width: f32, img_height: f32, stride: i32) -> (usize, usize) {
+    ((img_width / stride as f32).ceil() as usize, (img_height / stride as f32).ceil() as usize)
+}
+
+/// Center of the anchors at feature-map cell (`x`, `y`), in detector input pixels
+fn anchor_center(x: usize, y: usize, stride: i32) -> (f32, f32) {
+    ((x as f32 + 0.5) * stride as f32, (y as f32 + 0.5) * stride as f32)
+}
+
 /// Pre-computes output mappings at startup for efficient runtime inference.
 ///
 /// Runs the detector model once with dummy input to determine which outputs
@@ -185,22 +431,24 @@ fn decode_proposals(
 ///
 /// # Arguments
 /// * `session` - Mutable reference to the detector session
-/// * `output_names` - List of model output names
+/// * `detector_metadata` - Metadata discovered from the model (input name and layout, output names)
 /// * `strides` - List of detection strides (e.g., [8, 16, 32])
 /// * `target_height` - Model input height
 /// * `target_width` - Model input width
+/// * `num_anchors` - Anchors per feature-map location
 ///
 /// # Returns
-/// * `Ok(HashMap)` - Mapping from stride to (score_idx, bbox_idx, kps_idx)
+/// * `Ok(HashMap)` - Mapping from stride to (score_idx, bbox_idx, kps_idx), where
+///   `kps_idx` is `None` if the model has no keypoint outputs
 /// * `Err(AppError)` - If mapping computation fails
 pub fn match_outputs_by_shape_at_startup(
-    session: &mut Session,
-    output_names: &[String],
+    session: &Session,
+    detector_metadata: &ModelMetadata,
     strides: &[i32],
     target_height: u32,
     target_width: u32,
-) -> Result<std::collections::HashMap<i32, (usize, usize, usize)>, AppError> {
-    use ndarray::Array4;
+    num_anchors: usize,
+) -> Result<crate::config::StrideOutputMapping, AppError> {
     use ort::value::Value;
 
     // Safety check: ensure dimensions are reasonable
@@ -211,26 +459,38 @@ pub fn match_outputs_by_shape_at_startup(
     }
 
     // Create dummy input tensor
-    let input_array = Array4::<f32>::zeros((1, 3, target_height as usize, target_width as usize));
+    let input_array = detector_metadata.input_layout.arrange(Array4::<f32>::zeros((1, 3, target_height as usize, target_width as usize)));
     let input_tensor = Value::from_array(input_array)?;
 
     // Run inference to get output shapes
-    let outputs = session.run(ort::inputs!["input.1" => input_tensor]?)?;
+    let outputs = session.run(ort::inputs![&detector_metadata.input_name => input_tensor]?)?;
 
     // Extract all outputs with their shapes
     let mut extracted_outputs = Vec::new();
-    for output_name in output_names {
+    for output_name in &detector_metadata.output_names {
         let tensor = outputs[output_name.as_str()].try_extract_tensor::<f32>()?;
         let shape = tensor.shape().to_vec();
         extracted_outputs.push((output_name.clone(), tensor, shape));
     }
 
+    // Fewer outputs than a score and a bbox per stride: every stride is concatenated
+    // into one score, bbox and (optional) keypoint output
+    if strides.len() > 1 && extracted_outputs.len() < 2 * strides.len() {
+        let output_shapes: Vec<Vec<usize>> = extracted_outputs.into_iter().map(|(_, _, shape)| shape).collect();
+        return match_combined_outputs(&output_shapes, strides, target_height, target_width, num_anchors);
+    }
+
     // Match outputs for each stride
-    let mut stride_output_mapping = std::collections::HashMap::new();
+    let mut stride_output_mapping = crate::config::StrideOutputMapping::new();
 
     for &stride in strides {
-        if let Some((score_idx, bbox_idx, kps_idx)) = match_outputs_by_shape(&extracted_outputs, stride, target_height, target_width)? {
+        if let Some((score_idx, bbox_idx, kps_idx)) = match_outputs_by_shape(&extracted_outputs, stride, target_height, target_width, num_anchors)? {
             stride_output_mapping.insert(stride, (score_idx, bbox_idx, kps_idx));
+        } else if let Some(implied) = implied_anchor_count(extracted_outputs.iter().map(|(_, _, shape)| shape.as_slice()), stride, target_height, target_width, num_anchors) {
+            return Err(AppError::BadRequest(format!(
+                "Outputs for stride {} imply {} anchors per location, but models.detector.num_anchors is {}",
+                stride, implied, num_anchors
+            )));
         } else {
             return Err(AppError::BadRequest(format!("Could not find matching outputs for stride {}", stride)));
         }
@@ -240,26 +500,38 @@ pub fn match_outputs_by_shape_at_startup(
         return Err(AppError::BadRequest("No valid output mappings found for any stride".to_string()));
     }
 
+    // Keypoints are all-or-nothing: a partial set means the heuristic mismatched something
+    let strides_with_kps = stride_output_mapping.values().filter(|(_, _, kps)| kps.is_some()).count();
+    if strides_with_kps == 0 {
+        tracing::info!("Detector has no keypoint outputs; faces will be reported without landmarks");
+    } else if strides_with_kps < stride_output_mapping.len() {
+        return Err(AppError::BadRequest(format!(
+            "Keypoint outputs found for only {} of {} strides",
+            strides_with_kps,
+            stride_output_mapping.len()
+        )));
+    }
+
     tracing::info!("Pre-computed output mappings for {} strides", stride_output_mapping.len());
 
     Ok(stride_output_mapping)
 }
 
 /// Match outputs by their shapes to determine which is score, bbox, and keypoints for a given stride
-/// Returns indices into the extracted_outputs array
+/// Returns indices into the extracted_outputs array. Keypoints are optional, since some
+/// SCRFD exports only have score and bbox outputs.
 fn match_outputs_by_shape(
     extracted_outputs: &[(String, ArrayBase<ViewRepr<&f32>, Dim<IxDynImpl>>, Vec<usize>)],
     stride: i32,
     target_height: u32,
     target_width: u32,
-) -> Result<Option<(usize, usize, usize)>, AppError> {
+    num_anchors: usize,
+) -> Result<Option<(usize, usize, Option<usize>)>, AppError> {
 
     // Calculate expected number of anchors for this stride
-    // SCRFD typically uses 2 anchors per spatial location
     let feat_h = target_height / stride as u32;
     let feat_w = target_width / stride as u32;
-    let num_anchors_per_location = 2;
-    let expected_total_anchors = feat_h * feat_w * num_anchors_per_location;
+    let expected_total_anchors = feat_h * feat_w * num_anchors as u32;
 
 
 
@@ -303,18 +575,91 @@ fn match_outputs_by_shape(
         }
     }
 
-    // Return the matched indices if we found all three
-    if let (Some(score), Some(bbox), Some(kps)) = (score_idx, bbox_idx, kps_idx) {
-        Ok(Some((score, bbox, kps)))
+    // Score and bbox are required, keypoints are optional
+    if let (Some(score), Some(bbox)) = (score_idx, bbox_idx) {
+        Ok(Some((score, bbox, kps_idx)))
     } else {
         Ok(None)
     }
 }
 
+/// Match the outputs of a detector exported with every stride concatenated, shaped
+/// `[N, C]` or `[1, N, C]` where N is the anchor count of all strides together. Every
+/// stride is mapped to the same outputs; `run_detector` splits them by stride.
+fn match_combined_outputs(
+    output_shapes: &[Vec<usize>],
+    strides: &[i32],
+    target_height: u32,
+    target_width: u32,
+    num_anchors: usize,
+) -> Result<crate::config::StrideOutputMapping, AppError> {
+    let total_anchors: usize = strides
+        .iter()
+        .map(|&stride| {
+            let (feature_width, feature_height) = feature_map_size(target_width as f32, target_height as f32, stride);
+            feature_width * feature_height * num_anchors
+        })
+        .sum();
+
+    let output_with_channels = |channels: usize| {
+        output_shapes.iter().position(|shape| match shape.as_slice() {
+            [rows, c] | [1, rows, c] => *rows == total_anchors && *c == channels,
+            _ => false,
+        })
+    };
+
+    let (Some(score_idx), Some(bbox_idx)) = (output_with_channels(1), output_with_channels(4)) else {
+        return Err(AppError::BadRequest(format!(
+            "Detector has {} outputs, too few for separate outputs per stride, but no combined score and bbox outputs with {} rows for strides {:?} and {} anchors (output shapes: {:?})",
+            output_shapes.len(),
+            total_anchors,
+            strides,
+            num_anchors,
+            output_shapes
+        )));
+    };
+    let kps_idx = output_with_channels(10);
+    if kps_idx.is_none() {
+        tracing::info!("Detector has no keypoint outputs; faces will be reported without landmarks");
+    }
+
+    tracing::info!("Detector outputs combine all {} strides ({} anchors)", strides.len(), total_anchors);
+    Ok(strides.iter().map(|&stride| (stride, (score_idx, bbox_idx, kps_idx))).collect())
+}
+
+/// When no outputs match the configured anchor count for a stride, look for a
+/// score output whose row count is a different multiple of the feature-map size,
+/// so startup can report the anchor count the model actually uses.
+fn implied_anchor_count<'a>(
+    mut output_shapes: impl Iterator<Item = &'a [usize]>,
+    stride: i32,
+    target_height: u32,
+    target_width: u32,
+    num_anchors: usize,
+) -> Option<usize> {
+    let locations = ((target_height / stride as u32) * (target_width / stride as u32)) as usize;
+    if locations == 0 {
+        return None;
+    }
+
+    output_shapes.find_map(|shape| {
+        let is_score_output = shape.len() == 2 && shape[1] == 1;
+        let implied = shape[0] / locations;
+        (is_score_output && shape[0] % locations == 0 && implied != num_anchors && implied > 0).then_some(implied)
+    })
+}
+
 /// Applies Non-Maximum Suppression to filter overlapping boxes.
+/// NaN scores, which a misconfigured model can produce, rank below every other score.
 fn non_maximum_suppression(proposals: &[DetectedFace], iou_threshold: f32) -> Vec<DetectedFace> {
+    let nan_scores = proposals.iter().filter(|face| face.score.is_nan()).count();
+    if nan_scores > 0 {
+        tracing::warn!("{} detection proposals have NaN scores; check the detector model and its normalization", nan_scores);
+    }
+    let rank = |score: f32| if score.is_nan() { f32::NEG_INFINITY } else { score };
+
     let mut sorted_proposals = proposals.to_vec();
-    sorted_proposals.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    sorted_proposals.sort_by(|a, b| rank(b.score).total_cmp(&rank(a.score)));
     
     let mut keep_indices = Vec::new();
     let mut suppressed = vec![false; sorted_proposals.len()];
@@ -352,48 +697,465 @@ fn calculate_iou(box_a: &[f32; 4], box_b: &[f32; 4]) -> f32 {
     if union_area <= 0.0 { 0.0 } else { intersection_area / union_area }
 }
 
-/// Takes a detected face, crops, and generates a 512-dim embedding.
+/// Takes a detected face, crops, and generates an embedding of the recognizer's
+/// output dimension (see `ModelMetadata::embedding_dim`), L2-normalized unless
+/// `normalize_embeddings` is disabled. With `flip_tta` the mirrored
+/// crop is embedded too and both embeddings are summed before normalizing.
 pub fn get_recognition_embedding(
-    session: &mut Session,
+    session: &Session,
     original_image: &DynamicImage,
     face: &DetectedFace,
     recognizer_metadata: &ModelMetadata,
 ) -> Result<Vec<f32>, AppError> {
+    let input_tensor = prepare_recognition_input(original_image, face, recognizer_metadata);
+    let flipped = recognizer_metadata.flip_tta.then(|| flip_horizontal(&input_tensor));
+    let mut embedding = run_recognizer(session, input_tensor, recognizer_metadata)?;
+    if let Some(flipped) = flipped {
+        let mirrored = run_recognizer(session, flipped, recognizer_metadata)?;
+        embedding.iter_mut().zip(mirrored).for_each(|(v, m)| *v += m);
+    }
+
+    Ok(finish_embedding(embedding, recognizer_metadata))
+}
+
+/// Embeds several faces of the same image, stacking them into a single recognizer run
+/// when the model has a dynamic batch dimension. Fixed-batch models fall back to one
+/// run per face. Embeddings are returned in the order of `faces`.
+pub fn get_recognition_embeddings(
+    session: &Session,
+    original_image: &DynamicImage,
+    faces: &[DetectedFace],
+    recognizer_metadata: &ModelMetadata,
+) -> Result<Vec<Vec<f32>>, AppError> {
+    if !recognizer_metadata.dynamic_batch || faces.len() < 2 {
+        return faces
+            .iter()
+            .map(|face| get_recognition_embedding(session, original_image, face, recognizer_metadata))
+            .collect();
+    }
+
+    // With flip TTA each face is followed by its mirror image in the batch
+    let mut inputs: Vec<Array4<f32>> = Vec::new();
+    for face in faces {
+        let input = prepare_recognition_input(original_image, face, recognizer_metadata);
+        let flipped = recognizer_metadata.flip_tta.then(|| flip_horizontal(&input));
+        inputs.push(input);
+        inputs.extend(flipped);
+    }
+    let views: Vec<_> = inputs.iter().map(|input| input.view()).collect();
+    let batch = concatenate(Axis(0), &views)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to stack recognizer inputs: {}", e)))?;
+
+    let raw = run_recognizer(session, batch, recognizer_metadata)?;
+    if raw.len() != inputs.len() * recognizer_metadata.embedding_dim {
+        return Err(AppError::Internal(anyhow::anyhow!(
+            "Recognizer returned {} values for a batch of {} crops, expected {} per crop",
+            raw.len(), inputs.len(), recognizer_metadata.embedding_dim
+        )));
+    }
+    debug!("Embedded {} faces in one batch", faces.len());
+
+    let per_face = inputs.len() / faces.len();
+    Ok(raw
+        .chunks(recognizer_metadata.embedding_dim * per_face)
+        .map(|chunk| {
+            let mut embedding = chunk[..recognizer_metadata.embedding_dim].to_vec();
+            for mirrored in chunk.chunks(recognizer_metadata.embedding_dim).skip(1) {
+                embedding.iter_mut().zip(mirrored).for_each(|(v, m)| *v += m);
+            }
+            finish_embedding(embedding, recognizer_metadata)
+        })
+        .collect())
+}
+
+/// L2-normalizes a raw embedding unless `normalize_embeddings` is disabled
+fn finish_embedding(mut embedding: Vec<f32>, recognizer_metadata: &ModelMetadata) -> Vec<f32> {
+    if recognizer_metadata.normalize_embeddings {
+        let norm = l2_norm(&embedding);
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|v| *v /= norm);
+        }
+    }
+    embedding
+}
+
+/// Mirrors a `(N, C, H, W)` input tensor left to right
+fn flip_horizontal(tensor: &Array4<f32>) -> Array4<f32> {
+    tensor.slice(s![.., .., .., ..;-1]).to_owned()
+}
+
+/// Crops the face out of the image and builds the normalized recognizer input tensor.
+pub fn prepare_recognition_input(
+    original_image: &DynamicImage,
+    face: &DetectedFace,
+    recognizer_metadata: &ModelMetadata,
+) -> Array4<f32> {
     let (image_width, image_height) = original_image.dimensions();
-    let (x, y, width, height) = face.get_safe_crop_coords(image_width, image_height);
+    let (x, y, width, height) = face.get_safe_crop_coords(image_width, image_height, recognizer_metadata.crop_padding);
 
     let cropped_face = original_image.crop_imm(x, y, width, height);
 
     // Extract input size from recognizer metadata
     let input_size = recognizer_metadata.input_shape[2] as u32; // Assuming square input
 
-    let resized = cropped_face.resize_exact(
+    let mut resized = cropped_face.resize_exact(
         input_size,
         input_size,
-        image::imageops::FilterType::Triangle,
+        recognizer_metadata.interpolation.filter_type(),
     );
 
-    let mut input_tensor = Array::zeros((1, 3, input_size as usize, input_size as usize));
-    for (x, y, pixel) in resized.to_rgb8().enumerate_pixels() {
-        // Normalize pixel values: (pixel - mean) / scale, using BGR order for consistency
-        input_tensor[[0, 0, y as usize, x as usize]] = (pixel[2] as f32 - NORMALIZATION_MEAN) / NORMALIZATION_SCALE;
-        input_tensor[[0, 1, y as usize, x as usize]] = (pixel[1] as f32 - NORMALIZATION_MEAN) / NORMALIZATION_SCALE;
-        input_tensor[[0, 2, y as usize, x as usize]] = (pixel[0] as f32 - NORMALIZATION_MEAN) / NORMALIZATION_SCALE;
+    // Enlarging a small face blurs it; downsized crops are sharp enough already
+    if let Some(sharpen) = recognizer_metadata.sharpen {
+        if width < input_size || height < input_size {
+            resized = resized.unsharpen(sharpen.sigma, sharpen.threshold);
+        }
     }
 
+    image_to_tensor(&resized.to_rgb8(), &recognizer_metadata.normalization)
+}
+
+/// Runs the recognizer on a prepared NCHW input tensor and returns the raw (unnormalized) embedding.
+pub fn run_recognizer(
+    session: &Session,
+    input_tensor: Array4<f32>,
+    recognizer_metadata: &ModelMetadata,
+) -> Result<Vec<f32>, AppError> {
+    let input_tensor = recognizer_metadata.input_layout.arrange(input_tensor);
     let inputs = inputs![&recognizer_metadata.input_name => Value::from_array(input_tensor)?]?;
     let outputs = session.run(inputs)?;
 
     let output_name = &recognizer_metadata.output_names[0];
     let data = outputs[output_name.as_str()].try_extract_tensor::<f32>()?;
-    let mut embedding: Vec<f32> = data.iter().cloned().collect();
-    
-    let norm = (embedding.iter().map(|v| v.powi(2)).sum::<f32>()).sqrt();
-    if norm > 0.0 {
-        embedding.iter_mut().for_each(|v| *v /= norm);
+    Ok(data.iter().cloned().collect())
+}
+
+/// Cosine similarity of two vectors, 0 if either is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let norms = l2_norm(a) * l2_norm(b);
+    if norms == 0.0 {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>() / norms
+}
+
+/// Groups embeddings by average-linkage agglomerative clustering on cosine distance.
+///
+/// Starting from one cluster per embedding, the two closest clusters are merged as long
+/// as their average pairwise distance is at most `max_distance`. Returns a cluster label
+/// per embedding, numbered from 0 in order of first appearance.
+pub fn cluster_embeddings(embeddings: &[Vec<f32>], max_distance: f32) -> Vec<usize> {
+    let n = embeddings.len();
+    let mut distances = vec![vec![0.0f32; n]; n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let distance = 1.0 - cosine_similarity(&embeddings[i], &embeddings[j]);
+            distances[i][j] = distance;
+            distances[j][i] = distance;
+        }
+    }
+
+    let average_distance = |a: &[usize], b: &[usize]| {
+        let total: f32 = a.iter().flat_map(|&i| b.iter().map(move |&j| (i, j))).map(|(i, j)| distances[i][j]).sum();
+        total / (a.len() * b.len()) as f32
+    };
+
+    let mut clusters: Vec<Vec<usize>> = (0..n).map(|i| vec![i]).collect();
+    loop {
+        let mut closest: Option<(usize, usize, f32)> = None;
+        for a in 0..clusters.len() {
+            for b in (a + 1)..clusters.len() {
+                let distance = average_distance(&clusters[a], &clusters[b]);
+                if distance <= max_distance && closest.is_none_or(|(_, _, best)| distance < best) {
+                    closest = Some((a, b, distance));
+                }
+            }
+        }
+
+        let Some((a, b, _)) = closest else { break };
+        // b > a, so removing b leaves a's index intact
+        let merged = clusters.swap_remove(b);
+        clusters[a].extend(merged);
     }
 
-    Ok(embedding)
+    clusters.sort_by_key(|members| members.iter().min().copied());
+    let mut labels = vec![0; n];
+    for (label, members) in clusters.iter().enumerate() {
+        for &member in members {
+            labels[member] = label;
+        }
+    }
+    labels
+}
+
+/// Pairs `(i, j, similarity)` of embeddings with a cosine similarity of at least
+/// `threshold`, most similar first. Only the best `keep` pairs are returned, along with
+/// the number of all pairs above the threshold, so a low threshold over a large gallery
+/// doesn't hold every pair in memory.
+pub fn similar_pairs(embeddings: &[Vec<f32>], threshold: f32, keep: usize) -> (usize, Vec<(usize, usize, f32)>) {
+    let sort_and_truncate = |pairs: &mut Vec<(usize, usize, f32)>| {
+        pairs.sort_by(|a, b| b.2.total_cmp(&a.2));
+        pairs.truncate(keep);
+    };
+
+    // Normalized once, so each pair is a plain dot product
+    let normalized: Vec<Vec<f32>> = embeddings
+        .iter()
+        .map(|embedding| {
+            let norm = l2_norm(embedding);
+            embedding.iter().map(|v| if norm == 0.0 { 0.0 } else { v / norm }).collect()
+        })
+        .collect();
+
+    let mut total = 0;
+    let mut pairs = Vec::new();
+    for i in 0..normalized.len() {
+        for j in (i + 1)..normalized.len() {
+            let similarity: f32 = normalized[i].iter().zip(&normalized[j]).map(|(x, y)| x * y).sum();
+            if similarity >= threshold {
+                total += 1;
+                pairs.push((i, j, similarity));
+                if pairs.len() >= 2 * keep.max(1) {
+                    sort_and_truncate(&mut pairs);
+                }
+            }
+        }
+    }
+    sort_and_truncate(&mut pairs);
+    (total, pairs)
+}
+
+/// Follows faces across the frames of one video stream so their labels don't flicker
+/// and they needn't be recognized on every frame.
+///
+/// Each frame's faces are associated with the tracks of the previous frame by IoU,
+/// greedily from the best overlap down. A track's identity is the majority vote of its
+/// last `window` recognitions, so a single misrecognized frame doesn't change the label,
+/// and a followed face is only recognized again every `requery_interval` frames.
+pub struct FaceTracker {
+    tracks: Vec<Track>,
+    next_id: u64,
+    last_frame: Instant,
+}
+
+struct Track {
+    id: u64,
+    bbox: [f32; 4],
+    /// Frames since the face was last recognized
+    frames_since_query: u32,
+    /// Consecutive frames the face wasn't detected in
+    missed_frames: u32,
+    /// The most recent recognitions, oldest first; `None` when the face wasn't matched
+    recognitions: VecDeque<Option<(String, f32)>>,
+}
+
+/// The track a face of the current frame was assigned to
+#[derive(Debug, Clone, Copy)]
+pub struct TrackedFace {
+    pub track_id: u64,
+    /// The face is new or due to be recognized again
+    pub needs_query: bool,
+}
+
+impl Default for FaceTracker {
+    fn default() -> Self {
+        Self { tracks: Vec::new(), next_id: 0, last_frame: Instant::now() }
+    }
+}
+
+impl FaceTracker {
+    /// Time since the last frame of the stream
+    pub fn idle_for(&self) -> Duration {
+        self.last_frame.elapsed()
+    }
+
+    /// Assign the faces of a new frame to tracks, starting a track for each face that
+    /// doesn't overlap a tracked one. Returns one assignment per face, in order.
+    pub fn update(&mut self, faces: &[DetectedFace], config: &TrackingConfig) -> Vec<TrackedFace> {
+        self.last_frame = Instant::now();
+
+        let mut pairs = Vec::new();
+        for (track_index, track) in self.tracks.iter().enumerate() {
+            for (face_index, face) in faces.iter().enumerate() {
+                let iou = calculate_iou(&track.bbox, &face.bbox);
+                if iou >= config.iou_threshold {
+                    pairs.push((iou, track_index, face_index));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut track_of_face = vec![None; faces.len()];
+        let mut track_taken = vec![false; self.tracks.len()];
+        for (_, track_index, face_index) in pairs {
+            if !track_taken[track_index] && track_of_face[face_index].is_none() {
+                track_taken[track_index] = true;
+                track_of_face[face_index] = Some(track_index);
+            }
+        }
+        for (track, taken) in self.tracks.iter_mut().zip(track_taken) {
+            if !taken {
+                track.missed_frames += 1;
+            }
+        }
+
+        let mut assigned = Vec::with_capacity(faces.len());
+        for (face, track_index) in faces.iter().zip(track_of_face) {
+            let track = match track_index {
+                Some(track_index) => {
+                    let track = &mut self.tracks[track_index];
+                    track.bbox = face.bbox;
+                    track.missed_frames = 0;
+                    track.frames_since_query += 1;
+                    track
+                }
+                None => {
+                    self.next_id += 1;
+                    self.tracks.push(Track {
+                        id: self.next_id,
+                        bbox: face.bbox,
+                        frames_since_query: 0,
+                        missed_frames: 0,
+                        recognitions: VecDeque::new(),
+                    });
+                    self.tracks.last_mut().unwrap()
+                }
+            };
+            assigned.push(TrackedFace {
+                track_id: track.id,
+                needs_query: track.recognitions.is_empty() || track.frames_since_query >= config.requery_interval,
+            });
+        }
+        self.tracks.retain(|track| track.missed_frames <= config.max_missed_frames);
+        assigned
+    }
+
+    /// Record the outcome of recognizing a track's face: the matched person and
+    /// similarity, or `None` for an unknown face
+    pub fn record(&mut self, track_id: u64, recognition: Option<(String, f32)>, config: &TrackingConfig) {
+        if let Some(track) = self.tracks.iter_mut().find(|track| track.id == track_id) {
+            track.frames_since_query = 0;
+            track.recognitions.push_back(recognition);
+            while track.recognitions.len() > config.window {
+                track.recognitions.pop_front();
+            }
+        }
+    }
+
+    /// The smoothed identity of a track: the person most of its recent recognitions
+    /// matched, ties going to the most recent, with their mean similarity. `None` when
+    /// the face is mostly unknown or hasn't been recognized yet.
+    pub fn identity(&self, track_id: u64) -> Option<(String, f32)> {
+        let track = self.tracks.iter().find(|track| track.id == track_id)?;
+        let name_of = |recognition: &Option<(String, f32)>| recognition.as_ref().map(|(name, _)| name.clone());
+        let votes = |name: &Option<String>| track.recognitions.iter().filter(|recognition| name_of(recognition) == *name).count();
+
+        // max_by_key keeps the last of equal maxima, i.e. the most recent
+        let name = track.recognitions.iter().map(name_of).max_by_key(votes)??;
+        let similarities: Vec<f32> = track
+            .recognitions
+            .iter()
+            .flatten()
+            .filter(|(matched, _)| *matched == name)
+            .map(|(_, similarity)| *similarity)
+            .collect();
+        let similarity = similarities.iter().sum::<f32>() / similarities.len() as f32;
+        Some((name, similarity))
+    }
+}
+
+/// Relative height of the nose tip between the eye line (0) and the mouth line (1) on a
+/// frontal face, as in the ArcFace alignment template
+const FRONTAL_NOSE_HEIGHT: f32 = 0.5;
+
+/// Estimates the head pose from the 5 keypoints (left eye, right eye, nose, left and
+/// right mouth corner), or `None` if the face has none.
+///
+/// A rough geometric estimate, good enough to tell a frontal face from a profile: roll
+/// is the angle of the eye line, yaw how far the nose sits off the face's vertical center
+/// line relative to half the eye distance, and pitch how far it sits above or below its
+/// frontal height between eyes and mouth.
+pub fn estimate_pose(face: &DetectedFace) -> Option<Pose> {
+    let [left_eye, right_eye, nose, left_mouth, right_mouth] = face.keypoints()?;
+    let midpoint = |a: [f32; 2], b: [f32; 2]| [(a[0] + b[0]) / 2.0, (a[1] + b[1]) / 2.0];
+    let eyes = midpoint(left_eye, right_eye);
+    let mouth = midpoint(left_mouth, right_mouth);
+
+    let roll = (right_eye[1] - left_eye[1]).atan2(right_eye[0] - left_eye[0]);
+    // Undo the roll so the eye line is horizontal, with the eye midpoint as origin
+    let (sin, cos) = (-roll).sin_cos();
+    let upright = |point: [f32; 2]| {
+        let (x, y) = (point[0] - eyes[0], point[1] - eyes[1]);
+        [x * cos - y * sin, x * sin + y * cos]
+    };
+    let nose = upright(nose);
+    let mouth = upright(mouth);
+
+    let half_eye_distance = (right_eye[0] - left_eye[0]).hypot(right_eye[1] - left_eye[1]) / 2.0;
+    if half_eye_distance <= 0.0 || mouth[1] <= 0.0 {
+        return None;
+    }
+    let center_x = mouth[0] / 2.0;
+    let yaw = ((nose[0] - center_x) / half_eye_distance).clamp(-1.0, 1.0).asin();
+    let nose_height = nose[1] / mouth[1];
+    let pitch = ((nose_height - FRONTAL_NOSE_HEIGHT) / FRONTAL_NOSE_HEIGHT).clamp(-1.0, 1.0).asin();
+
+    Some(Pose {
+        yaw: yaw.to_degrees(),
+        pitch: pitch.to_degrees(),
+        roll: roll.to_degrees(),
+        frontalness: yaw.cos() * pitch.cos(),
+    })
+}
+
+/// Euclidean length of a vector
+pub fn l2_norm(values: &[f32]) -> f32 {
+    values.iter().map(|v| v.powi(2)).sum::<f32>().sqrt()
+}
+
+/// Renders a normalized `(1, 3, H, W)` input tensor back into an RGB image by undoing
+/// the normalization and channel reordering. Shows exactly what a model sees.
+pub fn tensor_to_image(input_tensor: &Array4<f32>, normalization: &NormalizationConfig) -> RgbImage {
+    let (_, _, height, width) = input_tensor.dim();
+    let channels = normalization.channel_order.source_indices();
+
+    let mut image = RgbImage::new(width as u32, height as u32);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        for (c, &target) in channels.iter().enumerate() {
+            let value = input_tensor[[0, c, y as usize, x as usize]] * normalization.std[c] + normalization.mean[c];
+            pixel[target] = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    image
+}
+
+/// Runs dummy inferences through both models so the first real request doesn't pay
+/// for lazy initialization and GPU kernel compilation. Returns the total time spent.
+pub fn warm_up(
+    detector_session: &Session,
+    recognizer_session: &Session,
+    detector_metadata: &crate::config::DetectorMetadata,
+    recognizer_metadata: &ModelMetadata,
+    iterations: u32,
+) -> Result<std::time::Duration, AppError> {
+    let start = std::time::Instant::now();
+
+    let height = detector_metadata.input_shape[2] as u32;
+    let width = detector_metadata.input_shape[3] as u32;
+    let blank = DynamicImage::new_rgb8(width, height);
+    let whole_image = DetectedFace {
+        bbox: [0.0, 0.0, width as f32, height as f32],
+        kps: [[0.0, 0.0]; 5],
+        score: 1.0,
+    };
+
+    for i in 0..iterations {
+        let iteration_start = std::time::Instant::now();
+        detect_faces(detector_session, &blank, &DebugParams::default(), detector_metadata)?;
+        get_recognition_embedding(recognizer_session, &blank, &whole_image, recognizer_metadata)?;
+        debug!("Warm-up iteration {} completed in {} ms", i + 1, iteration_start.elapsed().as_millis());
+    }
+
+    Ok(start.elapsed())
 }
 
 /// Creates a square cropped image of a face for gallery display
@@ -425,11 +1187,116 @@ pub fn create_gallery_crop(
     Ok(buffer.into_inner())
 }
 
+/// Draws the feature-map cells and anchor centers of one detector stride, mapped from the
+/// detector input onto `image` the same way detections are. Only the single-scale pass is
+/// shown; pyramid passes tile the enlarged image with grids of their own.
+pub fn draw_anchor_grid(
+    image: &mut DynamicImage,
+    stride: i32,
+    detector_metadata: &crate::config::DetectorMetadata,
+    scale_w: f32,
+    scale_h: f32,
+) {
+    let input_width = detector_metadata.input_shape[3] as f32;
+    let input_height = detector_metadata.input_shape[2] as f32;
+    let (feature_width, feature_height) = feature_map_size(input_width, input_height, stride);
+    debug!("Drawing {}x{} anchor grid of stride {}", feature_width, feature_height, stride);
+
+    let grid_color = Rgba([255u8, 255u8, 0u8, 255u8]);   // Yellow cell borders
+    let center_color = Rgba([255u8, 0u8, 255u8, 255u8]); // Magenta anchor centers
+    let to_image = |x: f32, y: f32| (x * scale_w - X_OFFSET, y * scale_h - Y_OFFSET);
+
+    for x in 0..=feature_width {
+        let edge = (x as i32 * stride) as f32;
+        draw_line_segment_mut(image, to_image(edge, 0.0), to_image(edge, input_height), grid_color);
+    }
+    for y in 0..=feature_height {
+        let edge = (y as i32 * stride) as f32;
+        draw_line_segment_mut(image, to_image(0.0, edge), to_image(input_width, edge), grid_color);
+    }
+    for y in 0..feature_height {
+        for x in 0..feature_width {
+            let (cx, cy) = anchor_center(x, y, stride);
+            let (cx, cy) = to_image(cx, cy);
+            draw_filled_circle_mut(image, (cx.round() as i32, cy.round() as i32), 2, center_color);
+        }
+    }
+}
+
+/// Short names of the 5 keypoints in model output order
+const KEYPOINT_LABELS: [&str; 5] = ["LE", "RE", "N", "LM", "RM"];
+
+/// Marks each keypoint of `results` with a dot and its index and name (0 LE = left eye,
+/// 1 RE = right eye, 2 N = nose, 3 LM / 4 RM = left / right mouth corner), to check that
+/// a detector's landmark order matches what alignment expects. Faces without keypoints
+/// are skipped.
+pub fn draw_keypoint_labels(image: &mut DynamicImage, results: &[FinalResult], font: &FontArc) {
+    const DOT_RADIUS: i32 = 3;
+    let dot_color = Rgba([0u8, 255u8, 0u8, 255u8]); // Green
+    let text_color = Rgba([255u8, 255u8, 0u8, 255u8]); // Yellow
+
+    for result in results {
+        let Some(keypoints) = result.detection.keypoints() else {
+            continue;
+        };
+        // Scale the labels with the face so they stay readable without covering it
+        let face_width = result.detection.bbox[2] - result.detection.bbox[0];
+        let font_scale = PxScale::from((face_width / 8.0).clamp(12.0, 24.0));
+        for (index, point) in keypoints.iter().enumerate() {
+            let center = (point[0].round() as i32, point[1].round() as i32);
+            draw_filled_circle_mut(image, center, DOT_RADIUS, dot_color);
+            let label = format!("{} {}", index, KEYPOINT_LABELS[index]);
+            draw_text_mut(image, text_color, center.0 + DOT_RADIUS + 2, center.1 - DOT_RADIUS, font_scale, font, &label);
+        }
+    }
+}
+
+/// A placeholder image with `message` word-wrapped onto it, shown where an image was
+/// expected but an error occurred
+pub fn render_message_image(message: &str, font: &FontArc) -> DynamicImage {
+    const WIDTH: u32 = 640;
+    const PADDING: u32 = 16;
+    const LINE_HEIGHT: u32 = 28;
+    let scale = PxScale::from(24.0);
+
+    let mut lines: Vec<String> = Vec::new();
+    for word in message.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if text_size(scale, font, &format!("{} {}", line, word)).0 <= WIDTH - 2 * PADDING => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+
+    let height = (lines.len() as u32 * LINE_HEIGHT + 2 * PADDING).max(120);
+    let mut image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(WIDTH, height, Rgba([48u8, 48u8, 48u8, 255u8])));
+    let text_color = Rgba([255u8, 110u8, 110u8, 255u8]);
+    for (i, line) in lines.iter().enumerate() {
+        let y = (PADDING + i as u32 * LINE_HEIGHT) as i32;
+        draw_text_mut(&mut image, text_color, PADDING as i32, y, scale, font, line);
+    }
+    image
+}
+
+/// Linear interpolation between the configured gradient colors, `similarity` clamped to [0, 1]
+fn gradient_color(drawing: &DrawingConfig, similarity: f32) -> Rgba<u8> {
+    let t = similarity.clamp(0.0, 1.0);
+    let channel = |i: usize| {
+        let (low, high) = (drawing.gradient_low[i] as f32, drawing.gradient_high[i] as f32);
+        (low + (high - low) * t).round() as u8
+    };
+    Rgba([channel(0), channel(1), channel(2), 255])
+}
+
 /// Draws bounding boxes and keypoints on an image.
 pub fn draw_detections(
     image: &mut DynamicImage,
     results: &[FinalResult],
     font: &FontArc,
+    unknown_label: &str,
+    drawing: &DrawingConfig,
 ) {
     debug!("Drawing {} detections on image", results.len());
 
@@ -449,19 +1316,19 @@ pub fn draw_detections(
         let width = (x2 - x1) as u32;
 
         // Determine if this is a known or unknown person
-        let (is_known, name, similarity_score) = match &result.recognition {
-            Some((name, score)) => {
-                if *score > 0.4 { // Only consider known if similarity is decent
-                    (true, name.clone(), Some(*score))
-                } else {
-                    (false, "Unknown".to_string(), Some(*score))
-                }
-            },
-            None => (false, "Unknown".to_string(), None),
+        let is_known = result.is_match;
+        let (name, similarity_score) = match &result.recognition {
+            Some((name, score)) if is_known => (name.clone(), Some(*score)),
+            Some((_, score)) => (unknown_label.to_string(), Some(*score)),
+            None => (unknown_label.to_string(), None),
         };
 
-        // Choose box color based on recognition status
-        let box_color = if is_known { blue_color } else { red_color };
+        // Choose box color based on recognition status, or on the similarity in gradient mode
+        let box_color = match drawing.color_mode {
+            ColorMode::Uniform if is_known => blue_color,
+            ColorMode::Uniform => red_color,
+            ColorMode::Gradient => gradient_color(drawing, similarity_score.unwrap_or(0.0)),
+        };
 
         // Draw Bounding Box with appropriate color
         for i in 0..THICKNESS {
@@ -476,16 +1343,24 @@ pub fn draw_detections(
         //     draw_filled_circle_mut(image, center, DOT_RADIUS, dot_color);
         // }
 
-        // --- Draw Text Label with Background and Similarity Score ---
-        let font_scale = PxScale::from(32.0);
+        // --- Draw Text Label with Background and Scores ---
+        // Name on the first line, similarity and optionally the detection score on the second
+        let mut lines = vec![name];
+        let scores: Vec<String> = similarity_score
+            .map(|score| format!("{:.3}", score))
+            .into_iter()
+            .chain(drawing.show_detection_score.then(|| format!("det {:.2}", face.score)))
+            .collect();
+        if !scores.is_empty() {
+            lines.push(scores.join(" "));
+        }
 
-        // Calculate the height of the text to size the background box
-        // Account for potential two lines (name + score)
-        let text_height = if similarity_score.is_some() { 64 } else { 32 }; // Double height for two lines
         let text_padding = 5; // Add some padding around the text
+        let font_scale = label_scale(font, &lines, width.saturating_sub(text_padding * 2));
+        let line_height = font_scale.y.ceil() as u32;
 
         // Define the filled rectangle for the background
-        let label_box_height = text_height + (text_padding * 2);
+        let label_box_height = line_height * lines.len() as u32 + (text_padding * 2);
         let label_box_rect = Rect::at(x1, y2)
             .of_size(width, label_box_height);
 
@@ -493,19 +1368,287 @@ pub fn draw_detections(
         draw_filled_rect_mut(image, label_box_rect, box_color);
 
         // Position and draw the text on top of the background
-        if let Some(score) = similarity_score {
-            // Draw name on first line
-            let name_position = (x1 + text_padding as i32, y2 + text_padding as i32);
-            draw_text_mut(image, text_color, name_position.0, name_position.1, font_scale, font, &name);
-
-            // Draw similarity score on second line
-            let score_text = format!("{:.3}", score);
-            let score_position = (x1 + text_padding as i32, y2 + text_padding as i32 + 32);
-            draw_text_mut(image, text_color, score_position.0, score_position.1, font_scale, font, &score_text);
-        } else {
-            // Draw just the name
-            let text_position = (x1 + text_padding as i32, y2 + text_padding as i32);
-            draw_text_mut(image, text_color, text_position.0, text_position.1, font_scale, font, &name);
+        for (i, line) in lines.iter().enumerate() {
+            let y = y2 + (text_padding + line_height * i as u32) as i32;
+            draw_text_mut(image, text_color, x1 + text_padding as i32, y, font_scale, font, line);
+        }
+    }
+}
+
+/// The label font size: 32px, shrunk so the widest line fits `max_width` on narrow boxes,
+/// but never below 12px
+fn label_scale(font: &FontArc, lines: &[String], max_width: u32) -> PxScale {
+    const SIZE: f32 = 32.0;
+    const MIN_SIZE: f32 = 12.0;
+    let widest = lines.iter().map(|line| text_size(PxScale::from(SIZE), font, line).0).max().unwrap_or(0);
+    if widest <= max_width {
+        return PxScale::from(SIZE);
+    }
+    PxScale::from((SIZE * max_width as f32 / widest as f32).max(MIN_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UploadFormat;
+
+    fn face(bbox: [f32; 4], score: f32) -> DetectedFace {
+        DetectedFace { bbox, kps: [[0.0; 2]; 5], score }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-6, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn iou_of_identical_boxes_is_one() {
+        assert_close(calculate_iou(&[10.0, 10.0, 50.0, 30.0], &[10.0, 10.0, 50.0, 30.0]), 1.0);
+    }
+
+    #[test]
+    fn iou_of_disjoint_boxes_is_zero() {
+        assert_close(calculate_iou(&[0.0, 0.0, 10.0, 10.0], &[20.0, 20.0, 30.0, 30.0]), 0.0);
+        // Boxes sharing only an edge don't overlap either
+        assert_close(calculate_iou(&[0.0, 0.0, 10.0, 10.0], &[10.0, 0.0, 20.0, 10.0]), 0.0);
+    }
+
+    #[test]
+    fn iou_of_partial_overlap() {
+        // 5x10 intersection of two 10x10 boxes: 50 / (100 + 100 - 50)
+        assert_close(calculate_iou(&[0.0, 0.0, 10.0, 10.0], &[5.0, 0.0, 15.0, 10.0]), 1.0 / 3.0);
+        // 5x5 intersection: 25 / (100 + 100 - 25)
+        assert_close(calculate_iou(&[0.0, 0.0, 10.0, 10.0], &[5.0, 5.0, 15.0, 15.0]), 25.0 / 175.0);
+        // A box inside another: 100 / 400
+        assert_close(calculate_iou(&[0.0, 0.0, 20.0, 20.0], &[5.0, 5.0, 15.0, 15.0]), 0.25);
+    }
+
+    #[test]
+    fn iou_of_degenerate_boxes_is_zero() {
+        assert_close(calculate_iou(&[5.0, 5.0, 5.0, 5.0], &[5.0, 5.0, 5.0, 5.0]), 0.0);
+    }
+
+    #[test]
+    fn nms_keeps_the_higher_scoring_of_two_overlapping_boxes() {
+        let proposals = [face([0.0, 0.0, 10.0, 10.0], 0.8), face([1.0, 0.0, 11.0, 10.0], 0.9)];
+        let kept = non_maximum_suppression(&proposals, 0.4);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].score, 0.9);
+        assert_eq!(kept[0].bbox, [1.0, 0.0, 11.0, 10.0]);
+    }
+
+    #[test]
+    fn nms_with_mixed_overlaps() {
+        // A overlaps B by IoU 0.82 and C by 1/3; B and C overlap by about 0.43
+        let a = face([0.0, 0.0, 10.0, 10.0], 0.9);
+        let b = face([1.0, 0.0, 11.0, 10.0], 0.8);
+        let c = face([5.0, 0.0, 15.0, 10.0], 0.7);
+        let kept = non_maximum_suppression(&[c.clone(), a.clone(), b.clone()], 0.4);
+        // B is suppressed by A; C survives A, and B no longer suppresses anything
+        let scores: Vec<f32> = kept.iter().map(|face| face.score).collect();
+        assert_eq!(scores, [0.9, 0.7]);
+
+        // At a threshold below 1/3, A suppresses both
+        let kept = non_maximum_suppression(&[c, a, b], 0.3);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].score, 0.9);
+    }
+
+    #[test]
+    fn nms_keeps_disjoint_boxes_in_score_order() {
+        let proposals = [face([0.0, 0.0, 10.0, 10.0], 0.7), face([20.0, 20.0, 30.0, 30.0], 0.95)];
+        let scores: Vec<f32> = non_maximum_suppression(&proposals, 0.4).iter().map(|face| face.score).collect();
+        assert_eq!(scores, [0.95, 0.7]);
+    }
+
+    #[test]
+    fn nms_ranks_nan_scores_last() {
+        let proposals = [face([0.0, 0.0, 10.0, 10.0], f32::NAN), face([0.0, 0.0, 10.0, 10.0], 0.6)];
+        let kept = non_maximum_suppression(&proposals, 0.4);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].score, 0.6);
+    }
+
+    #[test]
+    fn combined_outputs_are_matched_and_split_by_stride() {
+        // A 32x32 input with strides 8 and 16 and 2 anchors has 32 + 8 rows
+        let shapes = vec![vec![1, 40, 10], vec![40, 1], vec![1, 40, 4]];
+        let mapping = match_combined_outputs(&shapes, &[8, 16], 32, 32, 2).unwrap();
+        assert_eq!(mapping[&8], (1, 2, Some(0)));
+        assert_eq!(mapping[&16], (1, 2, Some(0)));
+        assert!(match_combined_outputs(&shapes, &[8, 16], 32, 32, 1).is_err());
+
+        let values: Vec<f32> = (0..40 * 4).map(|v| v as f32).collect();
+        let tensor = Array::from_shape_vec(vec![1, 40, 4], values).unwrap();
+        let rows = stride_rows(tensor.view(), "bbox", 32..40).unwrap();
+        assert_eq!(rows.shape(), &[8, 4]);
+        assert_eq!(rows[[0, 0]], 128.0);
+        assert!(stride_rows(tensor.view(), "bbox", 32..41).is_err());
+    }
+
+    #[test]
+    fn similar_pairs_keeps_the_most_similar() {
+        let embeddings = vec![vec![1.0, 0.0], vec![0.9, 0.1], vec![0.0, 1.0], vec![2.0, 0.0], vec![0.0, 0.0]];
+        let (total, pairs) = similar_pairs(&embeddings, 0.5, 2);
+        // 0-1, 0-3 and 1-3 are similar; the zero vector matches nothing
+        assert_eq!(total, 3);
+        assert_eq!(pairs.len(), 2);
+        assert_eq!((pairs[0].0, pairs[0].1), (0, 3));
+        assert_close(pairs[0].2, 1.0);
+        assert!(pairs[1].2 <= pairs[0].2);
+        assert!(pairs.iter().all(|&(i, j, _)| i < j));
+    }
+
+    #[test]
+    fn perceptual_hash_survives_recompression() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x * y) % 256) as u8])
+        }));
+        let mut jpeg = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg).unwrap();
+        let recompressed = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!(perceptual_hash(&image), perceptual_hash(&recompressed));
+
+        let mirrored = image.fliph();
+        assert_ne!(perceptual_hash(&image), perceptual_hash(&mirrored));
+    }
+
+    #[test]
+    fn tracker_follows_a_moving_face() {
+        let config = TrackingConfig::default();
+        let mut tracker = FaceTracker::default();
+        let first = tracker.update(&[face([0.0, 0.0, 10.0, 10.0], 0.9), face([50.0, 50.0, 60.0, 60.0], 0.9)], &config);
+        assert!(first.iter().all(|tracked| tracked.needs_query));
+        tracker.record(first[0].track_id, Some(("Alice".to_string(), 0.8)), &config);
+
+        // The first face moved slightly, the second left and a new one appeared
+        let second = tracker.update(&[face([100.0, 0.0, 110.0, 10.0], 0.9), face([1.0, 0.0, 11.0, 10.0], 0.9)], &config);
+        assert!(second[0].needs_query);
+        assert_ne!(second[0].track_id, first[1].track_id);
+        assert_eq!(second[1].track_id, first[0].track_id);
+        assert!(!second[1].needs_query);
+        assert_eq!(tracker.identity(second[1].track_id), Some(("Alice".to_string(), 0.8)));
+    }
+
+    #[test]
+    fn tracker_identity_is_the_majority_of_recent_recognitions() {
+        let config = TrackingConfig { window: 3, ..TrackingConfig::default() };
+        let mut tracker = FaceTracker::default();
+        let track_id = tracker.update(&[face([0.0, 0.0, 10.0, 10.0], 0.9)], &config)[0].track_id;
+
+        tracker.record(track_id, Some(("Alice".to_string(), 0.7)), &config);
+        tracker.record(track_id, Some(("Bob".to_string(), 0.6)), &config);
+        // A tie goes to the most recent recognition
+        assert_eq!(tracker.identity(track_id), Some(("Bob".to_string(), 0.6)));
+
+        tracker.record(track_id, Some(("Alice".to_string(), 0.9)), &config);
+        let (name, similarity) = tracker.identity(track_id).unwrap();
+        assert_eq!(name, "Alice");
+        assert!((similarity - 0.8).abs() < 1e-6);
+
+        // The window drops the oldest Alice, leaving Bob against Alice and an unknown
+        tracker.record(track_id, None, &config);
+        assert_eq!(tracker.identity(track_id), None);
+    }
+
+    /// CRC-32 of a PNG chunk's type and data
+    fn png_crc(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn oversized_images_are_rejected_from_the_header() {
+        // A few dozen bytes declaring a 4000x4000 RGB image, with garbage for pixel data
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut ihdr = b"IHDR".to_vec();
+        ihdr.extend_from_slice(&4000u32.to_be_bytes());
+        ihdr.extend_from_slice(&4000u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+        for chunk in [ihdr, b"IDATgarbage".to_vec(), b"IEND".to_vec()] {
+            png.extend_from_slice(&(chunk.len() as u32 - 4).to_be_bytes());
+            png.extend_from_slice(&chunk);
+            png.extend_from_slice(&png_crc(&chunk).to_be_bytes());
         }
+
+        let config = ImageConfig { max_pixels: 1_000_000, ..ImageConfig::default() };
+        // Decoding the pixel data would fail with an image error instead
+        assert!(matches!(decode_image(&png, &config), Err(AppError::ImageTooLarge(_))));
+        let config = ImageConfig { max_pixels: 16_000_000, ..ImageConfig::default() };
+        assert!(matches!(decode_image(&png, &config), Err(AppError::ImageReadError(_))));
+    }
+
+    #[test]
+    fn exif_orientation_is_applied() {
+        // Stored 32x16 with the left half red and the right half blue
+        let stored = RgbImage::from_fn(32, 16, |x, _| if x < 16 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) });
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(stored).write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg).unwrap();
+
+        // An APP1 segment right after SOI, holding a big-endian TIFF header with one IFD
+        // entry: Orientation (0x0112), SHORT, 6 = rotate 90° clockwise to display
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+        let mut app1 = vec![0xff, 0xe1];
+        app1.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        app1.extend_from_slice(&exif);
+        jpeg.splice(2..2, app1);
+
+        let image = decode_image(&jpeg, &ImageConfig::default()).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (16, 32));
+        // The left half ends up on top
+        let top = image.get_pixel(8, 8);
+        let bottom = image.get_pixel(8, 24);
+        assert!(top[0] > 200 && top[2] < 50, "top is {:?}", top);
+        assert!(bottom[2] > 200 && bottom[0] < 50, "bottom is {:?}", bottom);
     }
-}
\ No newline at end of file
+
+    fn encode_png(image: DynamicImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn grayscale_16_bit_and_alpha_images_decode_to_8_bit_rgb() {
+        let inputs = [
+            (DynamicImage::ImageLuma8(image::GrayImage::from_pixel(4, 4, image::Luma([100]))), [100, 100, 100]),
+            (DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(4, 4, image::Rgb([65535, 0, 32896]))), [255, 0, 128]),
+            (DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 40]))), [10, 20, 30]),
+        ];
+        for (input, expected) in inputs {
+            let color = input.color();
+            let decoded = decode_image(&encode_png(input), &ImageConfig::default()).unwrap();
+            assert_eq!(decoded.color(), image::ColorType::Rgb8, "{:?} input", color);
+            assert_eq!(decoded.as_rgb8().unwrap().get_pixel(1, 1).0, expected, "{:?} input", color);
+        }
+    }
+
+    #[test]
+    fn animated_gifs_decode_to_their_first_frame() {
+        let frame = |color: [u8; 4]| image::Frame::new(RgbaImage::from_pixel(8, 8, Rgba(color)));
+        let mut gif = Vec::new();
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+            encoder.encode_frames([frame([255, 0, 0, 255]), frame([0, 0, 255, 255])]).unwrap();
+        }
+
+        let config = ImageConfig { allowed_formats: vec![UploadFormat::Gif], ..ImageConfig::default() };
+        let image = decode_image(&gif, &config).unwrap();
+        assert_eq!(image.dimensions(), (8, 8));
+        assert_eq!(image.as_rgb8().unwrap().get_pixel(4, 4).0, [255, 0, 0]);
+    }
+
+    #[test]
+    fn boxes_past_the_image_edge_are_clamped() {
+        let face = face([-12.4, 30.6, 655.2, 500.0], 0.9);
+        assert_eq!(face.clamp_to_image(640, 480), [0.0, 31.0, 640.0, 480.0]);
+        // The face itself keeps the unclamped box for cropping
+        assert_eq!(face.bbox, [-12.4, 30.6, 655.2, 500.0]);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::auth::provided_api_key;
+use crate::config::RateLimitConfig;
+use crate::error::AppError;
+use crate::AppState;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+// Upper bound on tracked clients; idle buckets are pruned when it's reached, then the
+// least recently used ones are evicted
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Token-bucket rate limiter keyed by client identity.
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    requests_per_second: f64,
+    burst: f64,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+            requests_per_second: config.requests_per_second,
+            burst: config.burst as f64,
+        }
+    }
+
+    /// Take a token for `key`, returning `false` when the client is over its limit
+    pub fn check(&self, key: &str) -> bool {
+        let now = Instant::now();
+        // Buckets are only ever refilled or drained, so one left by a panic is still valid
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(key) {
+            // Buckets that would be full again carry no state worth keeping
+            let full_after = self.burst / self.requests_per_second;
+            buckets.retain(|_, bucket| now.duration_since(bucket.last_refill).as_secs_f64() < full_after);
+            if buckets.len() >= MAX_TRACKED_CLIENTS {
+                // Every bucket is in use: evict the least recently used tenth at once, so the
+                // scan isn't repeated for each new client
+                let mut last_refills: Vec<Instant> = buckets.values().map(|bucket| bucket.last_refill).collect();
+                let (_, &mut cutoff, _) = last_refills.select_nth_unstable(MAX_TRACKED_CLIENTS / 10);
+                buckets.retain(|_, bucket| bucket.last_refill > cutoff);
+            }
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Middleware rejecting clients that exceed the configured request rate with 429.
+/// Clients are identified by their API key when authentication is enabled, since
+/// `require_api_key` has validated it by then, and otherwise by IP address. Unvalidated
+/// keys are ignored, as a client could send a new one with every request.
+pub async fn rate_limit(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(limiter) = &state.rate_limiter else {
+        return Ok(next.run(request).await);
+    };
+
+    let validated_key = provided_api_key(request.headers()).filter(|_| !state.config.auth.api_keys.is_empty());
+    let connect_info = request.extensions().get::<ConnectInfo<SocketAddr>>();
+    let client = match (validated_key, connect_info) {
+        (Some(key), _) => format!("key:{}", key),
+        (None, Some(ConnectInfo(addr))) => format!("ip:{}", addr.ip()),
+        // Served without connection info (e.g. embedded in another router), clients can't
+        // be told apart and share one bucket
+        (None, None) => "unknown".to_string(),
+    };
+
+    if !limiter.check(&client) {
+        return Err(AppError::TooManyRequests("Rate limit exceeded, slow down".to_string()));
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracked_clients_are_capped() {
+        let limiter = RateLimiter::new(&RateLimitConfig { enabled: true, requests_per_second: 1.0, burst: 5 });
+        for client in 0..MAX_TRACKED_CLIENTS + 100 {
+            assert!(limiter.check(&format!("ip:{}", client)));
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.len() <= MAX_TRACKED_CLIENTS);
+        // The most recent clients are kept
+        assert!(buckets.contains_key(&format!("ip:{}", MAX_TRACKED_CLIENTS + 99)));
+    }
+}
@@ -0,0 +1,80 @@
+use ort::session::Session;
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard};
+
+/// An inference session shared by all requests.
+///
+/// ONNX Runtime allows concurrent `Run` calls on one session, so with
+/// `models.concurrent_inference` requests run the model side by side. Otherwise runs
+/// are serialized behind a mutex, which keeps at most one inference per model in
+/// flight and so bounds GPU memory use and per-request latency.
+pub enum SharedSession {
+    Exclusive(Mutex<Session>),
+    Concurrent(Session),
+}
+
+/// Access to a [`SharedSession`], held for the duration of an inference
+pub enum SessionGuard<'a> {
+    Locked(MutexGuard<'a, Session>),
+    Shared(&'a Session),
+}
+
+impl SharedSession {
+    pub fn new(session: Session, concurrent: bool) -> Self {
+        if concurrent {
+            SharedSession::Concurrent(session)
+        } else {
+            SharedSession::Exclusive(Mutex::new(session))
+        }
+    }
+
+    /// Access the session, waiting for earlier runs unless it is shared concurrently.
+    /// A panic during an earlier inference poisons the lock, but the session keeps no
+    /// per-request state, so it is recovered rather than left unusable.
+    pub fn acquire(&self) -> SessionGuard<'_> {
+        match self {
+            SharedSession::Exclusive(mutex) => SessionGuard::Locked(mutex.lock().unwrap_or_else(|poisoned| {
+                tracing::warn!("Recovering an inference session poisoned by an earlier panic");
+                mutex.clear_poison();
+                poisoned.into_inner()
+            })),
+            SharedSession::Concurrent(session) => SessionGuard::Shared(session),
+        }
+    }
+}
+
+impl Deref for SessionGuard<'_> {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        match self {
+            SessionGuard::Locked(guard) => guard,
+            SessionGuard::Shared(session) => session,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    fn a_session_poisoned_by_a_panic_is_recovered() {
+        let session = Session::builder().unwrap().commit_from_memory(&crate::tests::stub_recognizer("input")).unwrap();
+        let shared = SharedSession::new(session, false);
+
+        let panicked = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = shared.acquire();
+            panic!("inference failed");
+        }));
+        assert!(panicked.is_err());
+        let SharedSession::Exclusive(mutex) = &shared else { unreachable!() };
+        assert!(mutex.is_poisoned());
+
+        let guard = shared.acquire();
+        assert_eq!(guard.inputs[0].name, "input");
+        drop(guard);
+        assert!(!mutex.is_poisoned());
+    }
+}
@@ -0,0 +1,62 @@
+mod memory;
+mod sqlite;
+mod surreal;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use crate::audit::AuditEntry;
+use crate::error::AppError;
+use crate::models::{GalleryStatus, Person, PersonDetails, PersonSummary, RecognitionResult, StoredCrop, StoredEmbedding};
+use async_trait::async_trait;
+
+/// Storage for enrolled persons, abstracting the database behind the handlers.
+///
+/// Implemented by the SurrealDB connection ([`crate::db::Database`]), by
+/// [`SqliteStore`] for single-binary deployments, and by [`MemoryStore`], which
+/// keeps everything in process for tests and demos.
+/// Person ids are opaque strings such as "person:abc123" (SurrealDB uses the configured
+/// `database.person_table` as prefix); every method also accepts them without the prefix.
+#[async_trait]
+pub trait GalleryStore: Send + Sync {
+    /// Store a newly enrolled person and return its id and name
+    async fn enroll(&self, person: Person) -> Result<PersonSummary, AppError>;
+
+    /// The `limit` persons of `group` most similar to `embedding` by cosine similarity,
+    /// best first. `None` is the default gallery of persons enrolled without a group.
+    /// Records with a different embedding dimension are skipped.
+    async fn query_nearest(&self, embedding: &[f32], limit: usize, group: Option<&str>) -> Result<Vec<RecognitionResult>, AppError>;
+
+    /// One person's metadata, or `None` if no such person exists
+    async fn get(&self, id: &str) -> Result<Option<PersonDetails>, AppError>;
+
+    /// Every enrolled person, without embeddings
+    async fn list(&self) -> Result<Vec<StoredCrop>, AppError>;
+
+    /// Up to `limit` enrolled persons with their embeddings, skipping the first `offset`
+    /// in id order, so backups can page through the gallery
+    async fn export(&self, offset: usize, limit: usize) -> Result<Vec<Person>, AppError>;
+
+    /// Every enrolled person's id, name, group and embedding, without the gallery crop
+    async fn embeddings(&self) -> Result<Vec<StoredEmbedding>, AppError>;
+
+    /// Remove a person, returning `false` if no such person exists
+    async fn delete(&self, id: &str) -> Result<bool, AppError>;
+
+    /// Change a person's name, returning `None` if no such person exists
+    async fn rename(&self, id: &str, name: &str) -> Result<Option<PersonSummary>, AppError>;
+
+    /// Replace a person's embedding, e.g. after re-embedding with a new recognizer
+    async fn update_embedding(&self, id: &str, embedding: Vec<f32>) -> Result<(), AppError>;
+
+    /// Count the enrolled persons, and those whose embedding isn't `embedding_dim` long
+    async fn status(&self, embedding_dim: usize) -> Result<GalleryStatus, AppError>;
+
+    /// Append recognition audit entries to `table`
+    async fn append_audit(&self, table: &str, entries: &[AuditEntry]) -> Result<(), AppError>;
+}
+
+/// The key part of a person id, accepting both "abc123" and "person:abc123"
+fn person_key(id: &str) -> &str {
+    id.strip_prefix("person:").unwrap_or(id)
+}
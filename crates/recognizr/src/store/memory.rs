@@ -0,0 +1,151 @@
+use super::{person_key, GalleryStore};
+use crate::audit::{now_millis, AuditEntry};
+use crate::error::AppError;
+use crate::models::{GalleryStatus, Person, PersonDetails, PersonSummary, RecognitionResult, StoredCrop, StoredEmbedding};
+use crate::pipeline::cosine_similarity;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Gallery kept entirely in process memory. Nothing survives a restart, which makes
+/// it suitable for tests and demos that shouldn't need a running SurrealDB.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// Persons keyed by the key part of their id, in enrollment order
+    persons: Mutex<BTreeMap<u64, Person>>,
+    next_key: AtomicU64,
+    audit: Mutex<HashMap<String, Vec<AuditEntry>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn parse_key(id: &str) -> Option<u64> {
+    person_key(id).parse().ok()
+}
+
+fn person_id(key: u64) -> String {
+    format!("person:{}", key)
+}
+
+#[async_trait]
+impl GalleryStore for MemoryStore {
+    async fn enroll(&self, person: Person) -> Result<PersonSummary, AppError> {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed) + 1;
+        let summary = PersonSummary { id: person_id(key), name: person.name.clone() };
+        self.persons.lock().unwrap().insert(key, person);
+        Ok(summary)
+    }
+
+    async fn query_nearest(&self, embedding: &[f32], limit: usize, group: Option<&str>) -> Result<Vec<RecognitionResult>, AppError> {
+        let persons = self.persons.lock().unwrap();
+        let mut results: Vec<RecognitionResult> = persons
+            .values()
+            .filter(|person| person.embedding.len() == embedding.len() && person.group.as_deref() == group)
+            .map(|person| RecognitionResult {
+                name: person.name.clone(),
+                similarity: cosine_similarity(&person.embedding, embedding),
+                is_match: false,
+                bbox: None,
+                keypoints: None,
+                track_id: None,
+            })
+            .collect();
+        results.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<PersonDetails>, AppError> {
+        let Some(key) = parse_key(id) else {
+            return Ok(None);
+        };
+        let persons = self.persons.lock().unwrap();
+        Ok(persons.get(&key).map(|person| PersonDetails {
+            id: person_id(key),
+            name: person.name.clone(),
+            embedding_dim: person.embedding.len(),
+            created_at: person.created_at,
+            updated_at: person.updated_at,
+            group: person.group.clone(),
+        }))
+    }
+
+    async fn list(&self) -> Result<Vec<StoredCrop>, AppError> {
+        let persons = self.persons.lock().unwrap();
+        Ok(persons
+            .iter()
+            .map(|(&key, person)| StoredCrop {
+                id: person_id(key),
+                name: person.name.clone(),
+                cropped_image: person.cropped_image.clone(),
+                group: person.group.clone(),
+                created_at: person.created_at,
+                updated_at: person.updated_at,
+            })
+            .collect())
+    }
+
+    async fn export(&self, offset: usize, limit: usize) -> Result<Vec<Person>, AppError> {
+        Ok(self.persons.lock().unwrap().values().skip(offset).take(limit).cloned().collect())
+    }
+
+    async fn embeddings(&self) -> Result<Vec<StoredEmbedding>, AppError> {
+        let persons = self.persons.lock().unwrap();
+        Ok(persons
+            .iter()
+            .map(|(&key, person)| StoredEmbedding {
+                id: person_id(key),
+                name: person.name.clone(),
+                group: person.group.clone(),
+                embedding: person.embedding.clone(),
+            })
+            .collect())
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, AppError> {
+        let Some(key) = parse_key(id) else {
+            return Ok(false);
+        };
+        Ok(self.persons.lock().unwrap().remove(&key).is_some())
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<Option<PersonSummary>, AppError> {
+        let Some(key) = parse_key(id) else {
+            return Ok(None);
+        };
+        let mut persons = self.persons.lock().unwrap();
+        Ok(persons.get_mut(&key).map(|person| {
+            person.name = name.to_string();
+            person.updated_at = Some(now_millis());
+            PersonSummary { id: person_id(key), name: person.name.clone() }
+        }))
+    }
+
+    async fn update_embedding(&self, id: &str, embedding: Vec<f32>) -> Result<(), AppError> {
+        let mut persons = self.persons.lock().unwrap();
+        let person = parse_key(id)
+            .and_then(|key| persons.get_mut(&key))
+            .ok_or_else(|| AppError::NotFound(format!("Person {} does not exist", id)))?;
+        person.embedding = embedding;
+        person.updated_at = Some(now_millis());
+        Ok(())
+    }
+
+    async fn status(&self, embedding_dim: usize) -> Result<GalleryStatus, AppError> {
+        let persons = self.persons.lock().unwrap();
+        Ok(GalleryStatus {
+            persons: persons.len(),
+            incompatible: persons.values().filter(|person| person.embedding.len() != embedding_dim).count(),
+        })
+    }
+
+    async fn append_audit(&self, table: &str, entries: &[AuditEntry]) -> Result<(), AppError> {
+        self.audit.lock().unwrap().entry(table.to_string()).or_default().extend_from_slice(entries);
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use super::{person_key, GalleryStore};
+use crate::audit::{now_millis, AuditEntry};
+use crate::error::AppError;
+use crate::models::{GalleryStatus, Person, PersonDetails, PersonSummary, RecognitionResult, StoredCrop, StoredEmbedding};
+use crate::pipeline::cosine_similarity;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS person (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        embedding BLOB NOT NULL,
+        cropped_image BLOB NOT NULL,
+        \"group\" TEXT,
+        created_at INTEGER,
+        updated_at INTEGER
+    );
+";
+
+/// Gallery kept in a local SQLite file, for small installations that don't want to run
+/// SurrealDB. Embeddings are stored as little-endian `f32` blobs and compared in Rust
+/// by brute force, which is fast enough for galleries of a few thousand persons.
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database file and its schema
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)
+            .map_err(|e| anyhow::anyhow!("Could not open SQLite database {:?}: {}", path, e))?;
+        connection.execute_batch(SCHEMA)?;
+        add_missing_columns(&connection)?;
+        Ok(Self { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    /// Run blocking SQLite work off the async runtime
+    async fn with_connection<T, F>(&self, op: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || op(&mut connection.lock().unwrap()))
+            .await
+            .map_err(|e| AppError::Internal(e.into()))?
+            .map_err(AppError::from)
+    }
+}
+
+/// Columns added after the first release, missing from databases created before them
+const ADDED_COLUMNS: [(&str, &str); 3] = [("group", "TEXT"), ("created_at", "INTEGER"), ("updated_at", "INTEGER")];
+
+fn add_missing_columns(connection: &Connection) -> rusqlite::Result<()> {
+    for (column, column_type) in ADDED_COLUMNS {
+        let exists: bool = connection.query_row(
+            "SELECT count(*) > 0 FROM pragma_table_info('person') WHERE name = ?1",
+            params![column],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            connection.execute_batch(&format!("ALTER TABLE person ADD COLUMN \"{}\" {}", column, column_type))?;
+        }
+    }
+    Ok(())
+}
+
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+fn parse_key(id: &str) -> Option<i64> {
+    person_key(id).parse().ok()
+}
+
+fn person_id(key: i64) -> String {
+    format!("person:{}", key)
+}
+
+/// Timestamps are stored as signed integers, SQLite's only integer type
+fn to_sql_time(time: Option<u64>) -> Option<i64> {
+    time.map(|time| time as i64)
+}
+
+fn from_sql_time(time: Option<i64>) -> Option<u64> {
+    time.map(|time| time as u64)
+}
+
+#[async_trait]
+impl GalleryStore for SqliteStore {
+    async fn enroll(&self, person: Person) -> Result<PersonSummary, AppError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO person (name, embedding, cropped_image, \"group\", created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    person.name,
+                    encode_embedding(&person.embedding),
+                    person.cropped_image,
+                    person.group,
+                    to_sql_time(person.created_at),
+                    to_sql_time(person.updated_at),
+                ],
+            )?;
+            Ok(PersonSummary { id: person_id(connection.last_insert_rowid()), name: person.name })
+        })
+        .await
+    }
+
+    async fn query_nearest(&self, embedding: &[f32], limit: usize, group: Option<&str>) -> Result<Vec<RecognitionResult>, AppError> {
+        let query = embedding.to_vec();
+        let group = group.map(str::to_string);
+        self.with_connection(move |connection| {
+            // Blob length is 4 bytes per dimension; other dimensions can't be compared.
+            // IS matches NULL against NULL for the default gallery.
+            let mut statement =
+                connection.prepare("SELECT name, embedding FROM person WHERE length(embedding) = ?1 AND \"group\" IS ?2")?;
+            let rows = statement.query_map(params![query.len() * 4, group], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?;
+
+            let mut results = Vec::new();
+            for row in rows {
+                let (name, embedding) = row?;
+                results.push(RecognitionResult {
+                    name,
+                    similarity: cosine_similarity(&decode_embedding(&embedding), &query),
+                    is_match: false,
+                    bbox: None,
+                    keypoints: None,
+                    track_id: None,
+                });
+            }
+            results.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+            results.truncate(limit);
+            Ok(results)
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<PersonDetails>, AppError> {
+        let Some(key) = parse_key(id) else {
+            return Ok(None);
+        };
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT id, name, length(embedding) / 4, created_at, updated_at, \"group\" FROM person WHERE id = ?1",
+                    params![key],
+                    |row| {
+                        Ok(PersonDetails {
+                            id: person_id(row.get(0)?),
+                            name: row.get(1)?,
+                            embedding_dim: row.get::<_, i64>(2)? as usize,
+                            created_at: from_sql_time(row.get(3)?),
+                            updated_at: from_sql_time(row.get(4)?),
+                            group: row.get(5)?,
+                        })
+                    },
+                )
+                .optional()
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<StoredCrop>, AppError> {
+        self.with_connection(|connection| {
+            let mut statement = connection
+                .prepare("SELECT id, name, cropped_image, \"group\", created_at, updated_at FROM person ORDER BY id")?;
+            let rows = statement.query_map([], |row| {
+                Ok(StoredCrop {
+                    id: person_id(row.get(0)?),
+                    name: row.get(1)?,
+                    cropped_image: row.get(2)?,
+                    group: row.get(3)?,
+                    created_at: from_sql_time(row.get(4)?),
+                    updated_at: from_sql_time(row.get(5)?),
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn export(&self, offset: usize, limit: usize) -> Result<Vec<Person>, AppError> {
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT name, embedding, cropped_image, \"group\", created_at, updated_at FROM person ORDER BY id LIMIT ?1 OFFSET ?2",
+            )?;
+            let rows = statement.query_map(params![limit, offset], |row| {
+                Ok(Person {
+                    name: row.get(0)?,
+                    embedding: decode_embedding(&row.get::<_, Vec<u8>>(1)?),
+                    cropped_image: row.get(2)?,
+                    group: row.get(3)?,
+                    created_at: from_sql_time(row.get(4)?),
+                    updated_at: from_sql_time(row.get(5)?),
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn embeddings(&self) -> Result<Vec<StoredEmbedding>, AppError> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT id, name, \"group\", embedding FROM person ORDER BY id")?;
+            let rows = statement.query_map([], |row| {
+                Ok(StoredEmbedding {
+                    id: person_id(row.get(0)?),
+                    name: row.get(1)?,
+                    group: row.get(2)?,
+                    embedding: decode_embedding(&row.get::<_, Vec<u8>>(3)?),
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, AppError> {
+        let Some(key) = parse_key(id) else {
+            return Ok(false);
+        };
+        self.with_connection(move |connection| {
+            Ok(connection.execute("DELETE FROM person WHERE id = ?1", params![key])? > 0)
+        })
+        .await
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<Option<PersonSummary>, AppError> {
+        let Some(key) = parse_key(id) else {
+            return Ok(None);
+        };
+        let name = name.to_string();
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "UPDATE person SET name = ?1, updated_at = ?2 WHERE id = ?3 RETURNING id, name",
+                    params![name, now_millis() as i64, key],
+                    |row| Ok(PersonSummary { id: person_id(row.get(0)?), name: row.get(1)? }),
+                )
+                .optional()
+        })
+        .await
+    }
+
+    async fn update_embedding(&self, id: &str, embedding: Vec<f32>) -> Result<(), AppError> {
+        let key = parse_key(id).ok_or_else(|| AppError::NotFound(format!("Person {} does not exist", id)))?;
+        let updated = self
+            .with_connection(move |connection| {
+                connection.execute(
+                    "UPDATE person SET embedding = ?1, updated_at = ?2 WHERE id = ?3",
+                    params![encode_embedding(&embedding), now_millis() as i64, key],
+                )
+            })
+            .await?;
+        if updated == 0 {
+            return Err(AppError::NotFound(format!("Person {} does not exist", id)));
+        }
+        Ok(())
+    }
+
+    async fn status(&self, embedding_dim: usize) -> Result<GalleryStatus, AppError> {
+        self.with_connection(move |connection| {
+            let (persons, incompatible): (i64, i64) = connection.query_row(
+                "SELECT count(*), count(*) FILTER (WHERE length(embedding) != ?1) FROM person",
+                params![embedding_dim * 4],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            Ok(GalleryStatus { persons: persons as usize, incompatible: incompatible as usize })
+        })
+        .await
+    }
+
+    async fn append_audit(&self, table: &str, entries: &[AuditEntry]) -> Result<(), AppError> {
+        // The table name is validated as a plain identifier when the configuration is loaded
+        let table = table.to_string();
+        let entries = entries.to_vec();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS \"{}\" (timestamp INTEGER NOT NULL, name TEXT NOT NULL, similarity REAL NOT NULL, bbox TEXT)",
+                table
+            ))?;
+            {
+                let mut statement = transaction.prepare(&format!(
+                    "INSERT INTO \"{}\" (timestamp, name, similarity, bbox) VALUES (?1, ?2, ?3, ?4)",
+                    table
+                ))?;
+                for entry in &entries {
+                    let bbox = entry.bbox.map(|bbox| serde_json::json!(bbox).to_string());
+                    statement.execute(params![entry.timestamp as i64, entry.name, entry.similarity, bbox])?;
+                }
+            }
+            transaction.commit()
+        })
+        .await
+    }
+}
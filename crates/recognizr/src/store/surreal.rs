@@ -0,0 +1,274 @@
+use super::{person_key, GalleryStore};
+use crate::audit::{now_millis, AuditEntry};
+use crate::db::Database;
+use crate::error::AppError;
+use crate::models::{GalleryStatus, Person, PersonDetails, PersonSummary, RecognitionResult, StoredCrop, StoredEmbedding};
+use async_trait::async_trait;
+use serde::Deserialize;
+use surrealdb::RecordId;
+
+/// The id and name columns of a person record
+#[derive(Debug, Deserialize)]
+struct PersonRecord {
+    id: RecordId,
+    name: String,
+}
+
+impl From<PersonRecord> for PersonSummary {
+    fn from(record: PersonRecord) -> Self {
+        PersonSummary { id: record.id.to_string(), name: record.name }
+    }
+}
+
+/// A person record without its embedding
+#[derive(Debug, Deserialize)]
+struct CropRecord {
+    id: RecordId,
+    name: String,
+    cropped_image: Vec<u8>,
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    updated_at: Option<u64>,
+}
+
+/// A person record without its gallery crop
+#[derive(Debug, Deserialize)]
+struct EmbeddingRecord {
+    id: RecordId,
+    name: String,
+    #[serde(default)]
+    group: Option<String>,
+    embedding: Vec<f32>,
+}
+
+/// A person record's metadata, as returned by `GET /persons/{id}`
+#[derive(Debug, Deserialize)]
+struct DetailsRecord {
+    id: RecordId,
+    name: String,
+    embedding_dim: usize,
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    updated_at: Option<u64>,
+    #[serde(default)]
+    group: Option<String>,
+}
+
+impl Database {
+    /// The record id of a person, accepting the key alone or prefixed with the
+    /// configured table name (or "person:", as for the other stores)
+    fn person_record_id(&self, id: &str) -> RecordId {
+        let table = self.person_table();
+        let key = id
+            .strip_prefix(table)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .unwrap_or_else(|| person_key(id));
+        RecordId::from_table_key(table, key)
+    }
+}
+
+#[async_trait]
+impl GalleryStore for Database {
+    async fn enroll(&self, person: Person) -> Result<PersonSummary, AppError> {
+        let created: Option<PersonRecord> = self
+            .run(|db| {
+                let person = person.clone();
+                let table = self.person_table().to_string();
+                async move { db.create(table).content(person).await }
+            })
+            .await?;
+        let created = created.ok_or_else(|| AppError::Internal(anyhow::anyhow!("Database returned no record for the created person")))?;
+        Ok(created.into())
+    }
+
+    async fn query_nearest(&self, embedding: &[f32], limit: usize, group: Option<&str>) -> Result<Vec<RecognitionResult>, AppError> {
+        let mut response = self
+            .run(|db| {
+                let embedding = embedding.to_vec();
+                let table = self.person_table().to_string();
+                let group = group.map(str::to_string);
+                async move {
+                    let dim = embedding.len();
+                    // Persons of the default gallery have no group field, which NONE matches
+                    db.query("SELECT name, vector::similarity::cosine(embedding, $query) AS similarity FROM type::table($table) WHERE array::len(embedding) = $dim AND `group` = $group ORDER BY similarity DESC LIMIT $limit")
+                        .bind(("table", table))
+                        .bind(("group", group))
+                        .bind(("query", embedding))
+                        .bind(("dim", dim))
+                        .bind(("limit", limit))
+                        .await
+                }
+            })
+            .await?;
+        Ok(response.take(0)?)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<PersonDetails>, AppError> {
+        let record_id = self.person_record_id(id);
+        let mut response = self
+            .run(|db| {
+                let record_id = record_id.clone();
+                async move {
+                    db.query("SELECT id, name, array::len(embedding) AS embedding_dim, created_at, updated_at, `group` FROM $id")
+                        .bind(("id", record_id))
+                        .await
+                }
+            })
+            .await?;
+        let record: Option<DetailsRecord> = response.take(0)?;
+        Ok(record.map(|record| PersonDetails {
+            id: record.id.to_string(),
+            name: record.name,
+            embedding_dim: record.embedding_dim,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+            group: record.group,
+        }))
+    }
+
+    async fn list(&self) -> Result<Vec<StoredCrop>, AppError> {
+        let mut response = self
+            .run(|db| {
+                let table = self.person_table().to_string();
+                async move { db.query("SELECT id, name, cropped_image, `group`, created_at, updated_at FROM type::table($table)").bind(("table", table)).await }
+            })
+            .await?;
+        let records: Vec<CropRecord> = response.take(0)?;
+        Ok(records
+            .into_iter()
+            .map(|record| StoredCrop {
+                id: record.id.to_string(),
+                name: record.name,
+                cropped_image: record.cropped_image,
+                group: record.group,
+                created_at: record.created_at,
+                updated_at: record.updated_at,
+            })
+            .collect())
+    }
+
+    async fn export(&self, offset: usize, limit: usize) -> Result<Vec<Person>, AppError> {
+        let mut response = self
+            .run(|db| {
+                let table = self.person_table().to_string();
+                async move {
+                    db.query("SELECT name, embedding, cropped_image, `group`, created_at, updated_at FROM type::table($table) ORDER BY id LIMIT $limit START $offset")
+                        .bind(("table", table))
+                        .bind(("limit", limit))
+                        .bind(("offset", offset))
+                        .await
+                }
+            })
+            .await?;
+        Ok(response.take(0)?)
+    }
+
+    async fn embeddings(&self) -> Result<Vec<StoredEmbedding>, AppError> {
+        let mut response = self
+            .run(|db| {
+                let table = self.person_table().to_string();
+                async move { db.query("SELECT id, name, `group`, embedding FROM type::table($table)").bind(("table", table)).await }
+            })
+            .await?;
+        let records: Vec<EmbeddingRecord> = response.take(0)?;
+        Ok(records
+            .into_iter()
+            .map(|record| StoredEmbedding {
+                id: record.id.to_string(),
+                name: record.name,
+                group: record.group,
+                embedding: record.embedding,
+            })
+            .collect())
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, AppError> {
+        let record_id = self.person_record_id(id);
+        let mut response = self
+            .run(|db| {
+                let record_id = record_id.clone();
+                async move { db.query("DELETE $id RETURN BEFORE").bind(("id", record_id)).await }
+            })
+            .await?;
+        let deleted: Option<PersonRecord> = response.take(0)?;
+        Ok(deleted.is_some())
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<Option<PersonSummary>, AppError> {
+        let record_id = self.person_record_id(id);
+        let mut response = self
+            .run(|db| {
+                let record_id = record_id.clone();
+                let name = name.to_string();
+                async move {
+                    db.query("UPDATE $id SET name = $name, updated_at = $now RETURN id, name")
+                        .bind(("id", record_id))
+                        .bind(("name", name))
+                        .bind(("now", now_millis()))
+                        .await
+                }
+            })
+            .await?;
+        let updated: Option<PersonRecord> = response.take(0)?;
+        Ok(updated.map(PersonSummary::from))
+    }
+
+    async fn update_embedding(&self, id: &str, embedding: Vec<f32>) -> Result<(), AppError> {
+        let record_id = self.person_record_id(id);
+        self.run(|db| {
+            let record_id = record_id.clone();
+            let embedding = embedding.clone();
+            async move {
+                db.query("UPDATE $id SET embedding = $embedding, updated_at = $now")
+                    .bind(("id", record_id))
+                    .bind(("embedding", embedding))
+                    .bind(("now", now_millis()))
+                    .await?
+                    .check()
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn status(&self, embedding_dim: usize) -> Result<GalleryStatus, AppError> {
+        let mut response = self
+            .run(|db| {
+                let table = self.person_table().to_string();
+                async move {
+                    db.query("SELECT count() AS count FROM type::table($table) GROUP ALL")
+                        .query("SELECT count() AS count FROM type::table($table) WHERE array::len(embedding) != $dim GROUP ALL")
+                        .bind(("table", table))
+                        .bind(("dim", embedding_dim))
+                        .await
+                }
+            })
+            .await?;
+        let persons: Option<usize> = response.take((0, "count"))?;
+        let incompatible: Option<usize> = response.take((1, "count"))?;
+        Ok(GalleryStatus {
+            persons: persons.unwrap_or(0),
+            incompatible: incompatible.unwrap_or(0),
+        })
+    }
+
+    async fn append_audit(&self, table: &str, entries: &[AuditEntry]) -> Result<(), AppError> {
+        self.run(|db| {
+            let table = table.to_string();
+            let entries = entries.to_vec();
+            async move {
+                db.query("FOR $entry IN $entries { CREATE type::table($table) CONTENT $entry; }")
+                    .bind(("table", table))
+                    .bind(("entries", entries))
+                    .await?
+                    .check()
+            }
+        })
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+//! End-to-end tests of the HTTP API: the full router and handler stack over the
+//! in-memory gallery, with tiny stub ONNX models standing in for SCRFD and ArcFace.
+//!
+//! The stub detector has a single stride (32) on a 64x64 input, so 4 anchors. It
+//! reports two faces, at the top-left and bottom-right anchors with scores 0.9 and 0.8,
+//! scaled by the mean brightness of the normalized input: a white image has both faces,
+//! a black one none, and `?threshold=0.85` keeps only the first. The stub recognizer's
+//! embedding is the mean of each input channel, so faces cut from the same uniform
+//! image embed identically.
+//!
+//! The tests need the ONNX Runtime library, as the server does, and the configured font
+//! (`DejaVuSansMono.ttf` in the repository root, or `RECOGNIZR_FONT_PATH`).
+
+use crate::config::{Configuration, StorageBackend};
+use ab_glyph::FontArc;
+use axum::body::Body;
+use axum::extract::connect_info::MockConnectInfo;
+use axum::http::{Request, StatusCode};
+use image::{DynamicImage, Rgb, RgbImage};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tower::ServiceExt;
+
+/// Upload limit of the test configuration, small enough to exceed with a generated image
+const MAX_UPLOAD_BYTES: usize = 16 * 1024;
+const BOUNDARY: &str = "recognizr-test-boundary";
+
+/// A minimal ONNX protobuf writer, covering just the messages the stub models need
+mod onnx {
+    const VARINT: u64 = 0;
+    const LENGTH_DELIMITED: u64 = 2;
+    const FLOAT: i64 = 1;
+
+    fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buffer.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        buffer.push(value as u8);
+    }
+
+    fn int_field(buffer: &mut Vec<u8>, field: u64, value: i64) {
+        varint(buffer, field << 3 | VARINT);
+        varint(buffer, value as u64);
+    }
+
+    fn bytes_field(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(buffer, field << 3 | LENGTH_DELIMITED);
+        varint(buffer, bytes.len() as u64);
+        buffer.extend_from_slice(bytes);
+    }
+
+    /// A float `TensorProto` holding `values` as raw little-endian data
+    pub fn tensor(name: &str, dims: &[i64], values: &[f32]) -> Vec<u8> {
+        let mut tensor = Vec::new();
+        for &dim in dims {
+            int_field(&mut tensor, 1, dim);
+        }
+        int_field(&mut tensor, 2, FLOAT);
+        bytes_field(&mut tensor, 8, name.as_bytes());
+        let raw: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        bytes_field(&mut tensor, 9, &raw);
+        tensor
+    }
+
+    /// A `ValueInfoProto` declaring a float tensor of static shape
+    pub fn value_info(name: &str, dims: &[i64]) -> Vec<u8> {
+        let mut shape = Vec::new();
+        for &dim in dims {
+            let mut dimension = Vec::new();
+            int_field(&mut dimension, 1, dim);
+            bytes_field(&mut shape, 1, &dimension);
+        }
+        let mut tensor_type = Vec::new();
+        int_field(&mut tensor_type, 1, FLOAT);
+        bytes_field(&mut tensor_type, 2, &shape);
+        let mut type_proto = Vec::new();
+        bytes_field(&mut type_proto, 1, &tensor_type);
+
+        let mut info = Vec::new();
+        bytes_field(&mut info, 1, name.as_bytes());
+        bytes_field(&mut info, 2, &type_proto);
+        info
+    }
+
+    /// An `AttributeProto` holding a list of integers
+    pub fn ints_attribute(name: &str, values: &[i64]) -> Vec<u8> {
+        let mut attribute = Vec::new();
+        bytes_field(&mut attribute, 1, name.as_bytes());
+        for &value in values {
+            int_field(&mut attribute, 8, value);
+        }
+        int_field(&mut attribute, 20, 7); // INTS
+        attribute
+    }
+
+    /// An `AttributeProto` holding one integer
+    pub fn int_attribute(name: &str, value: i64) -> Vec<u8> {
+        let mut attribute = Vec::new();
+        bytes_field(&mut attribute, 1, name.as_bytes());
+        int_field(&mut attribute, 3, value);
+        int_field(&mut attribute, 20, 2); // INT
+        attribute
+    }
+
+    pub fn node(op_type: &str, inputs: &[&str], outputs: &[&str], attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut node = Vec::new();
+        for input in inputs {
+            bytes_field(&mut node, 1, input.as_bytes());
+        }
+        for output in outputs {
+            bytes_field(&mut node, 2, output.as_bytes());
+        }
+        bytes_field(&mut node, 4, op_type.as_bytes());
+        for attribute in attributes {
+            bytes_field(&mut node, 5, attribute);
+        }
+        node
+    }
+
+    /// A serialized `ModelProto` at opset 13, where `ReduceMean` still takes its axes
+    /// as an attribute
+    pub fn model(nodes: &[Vec<u8>], initializers: &[Vec<u8>], inputs: &[Vec<u8>], outputs: &[Vec<u8>]) -> Vec<u8> {
+        let mut graph = Vec::new();
+        for node in nodes {
+            bytes_field(&mut graph, 1, node);
+        }
+        bytes_field(&mut graph, 2, b"stub");
+        for initializer in initializers {
+            bytes_field(&mut graph, 5, initializer);
+        }
+        for input in inputs {
+            bytes_field(&mut graph, 11, input);
+        }
+        for output in outputs {
+            bytes_field(&mut graph, 12, output);
+        }
+
+        let mut opset = Vec::new();
+        bytes_field(&mut opset, 1, b"");
+        int_field(&mut opset, 2, 13);
+
+        let mut model = Vec::new();
+        int_field(&mut model, 1, 7); // IR version
+        bytes_field(&mut model, 2, b"recognizr-tests");
+        bytes_field(&mut model, 7, &graph);
+        bytes_field(&mut model, 8, &opset);
+        model
+    }
+}
+
+/// The stub SCRFD detector described in the module docs, taking its image as `input`
+fn stub_detector(input: &str) -> Vec<u8> {
+    // Per anchor: box distances and keypoint offsets from the anchor center, in strides.
+    // Every anchor gets the same 16px box with a frontal set of landmarks.
+    let bbox = [0.25, 0.25, 0.25, 0.25];
+    let kps = [-0.125, -0.09375, 0.125, -0.09375, 0.0, 0.03125, -0.09375, 0.15625, 0.09375, 0.15625];
+    onnx::model(
+        &[
+            onnx::node("ReduceMean", &[input], &["brightness"], &[onnx::ints_attribute("axes", &[1, 2, 3]), onnx::int_attribute("keepdims", 0)]),
+            onnx::node("Mul", &["face_scores", "brightness"], &["score_32"], &[]),
+            onnx::node("Identity", &["face_boxes"], &["bbox_32"], &[]),
+            onnx::node("Identity", &["face_kps"], &["kps_32"], &[]),
+        ],
+        &[
+            onnx::tensor("face_scores", &[4, 1], &[0.9, 0.0, 0.0, 0.8]),
+            onnx::tensor("face_boxes", &[4, 4], &bbox.repeat(4)),
+            onnx::tensor("face_kps", &[4, 10], &kps.repeat(4)),
+        ],
+        &[onnx::value_info(input, &[1, 3, 64, 64])],
+        &[
+            onnx::value_info("score_32", &[4, 1]),
+            onnx::value_info("bbox_32", &[4, 4]),
+            onnx::value_info("kps_32", &[4, 10]),
+        ],
+    )
+}
+
+/// The stub recognizer: a 3-dimensional embedding of the mean of each channel of `input`
+pub(crate) fn stub_recognizer(input: &str) -> Vec<u8> {
+    onnx::model(
+        &[onnx::node("ReduceMean", &[input], &["embedding"], &[onnx::ints_attribute("axes", &[2, 3]), onnx::int_attribute("keepdims", 0)])],
+        &[],
+        &[onnx::value_info(input, &[1, 3, 112, 112])],
+        &[onnx::value_info("embedding", &[1, 3])],
+    )
+}
+
+/// Directory holding the stub models, written once per test run. The `*_data.onnx`
+/// variants name their input `data`, as some InsightFace exports do.
+fn stub_models() -> &'static PathBuf {
+    static MODELS: OnceLock<PathBuf> = OnceLock::new();
+    MODELS.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("recognizr-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("detector.onnx"), stub_detector("input")).unwrap();
+        std::fs::write(dir.join("recognizer.onnx"), stub_recognizer("input")).unwrap();
+        std::fs::write(dir.join("detector_data.onnx"), stub_detector("data")).unwrap();
+        std::fs::write(dir.join("recognizer_data.onnx"), stub_recognizer("data")).unwrap();
+        dir
+    })
+}
+
+fn test_config() -> Configuration {
+    let mut config = Configuration::default();
+    let models = stub_models();
+    config.models.detector.path = models.join("detector.onnx");
+    config.models.detector.strides = vec![32];
+    config.models.detector.input_shape = [64, 64];
+    config.models.detector.num_anchors = 1;
+    config.models.recognizer.path = models.join("recognizer.onnx");
+    config.models.warmup_iterations = 0;
+    config.storage.backend = StorageBackend::Memory;
+    config.images.max_upload_bytes = MAX_UPLOAD_BYTES;
+    config
+}
+
+fn test_font() -> FontArc {
+    let path = std::env::var_os("RECOGNIZR_FONT_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../../DejaVuSansMono.ttf")));
+    let data = std::fs::read(&path).unwrap_or_else(|e| panic!("Tests need the font at {}: {}", path.display(), e));
+    FontArc::try_from_vec(data).unwrap()
+}
+
+/// The full router over a fresh in-memory gallery
+async fn app() -> axum::Router {
+    app_with(test_config()).await
+}
+
+/// The router as `main` serves it, with the client address the rate limiter keys on
+async fn app_with(config: Configuration) -> axum::Router {
+    let state = crate::build_state(&config, test_font(), "CPU").await.unwrap();
+    crate::handlers::create_router(state).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))))
+}
+
+fn encode_png(image: RgbImage) -> Vec<u8> {
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(image).write_to(&mut buffer, image::ImageFormat::Png).unwrap();
+    buffer.into_inner()
+}
+
+/// A 64x64 image of one gray level, as the detector sees it without letterboxing
+fn uniform_png(level: u8) -> Vec<u8> {
+    encode_png(RgbImage::from_pixel(64, 64, Rgb([level; 3])))
+}
+
+/// An image of pseudo-random pixels, which PNG can't compress below `MAX_UPLOAD_BYTES`
+fn noise_png() -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    let image = RgbImage::from_fn(128, 128, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        Rgb([r, g, b])
+    });
+    let png = encode_png(image);
+    assert!(png.len() > MAX_UPLOAD_BYTES);
+    png
+}
+
+/// POST a multipart form with an `image` file and optionally a `name`
+async fn post_form(app: axum::Router, uri: &str, name: Option<&str>, image: &[u8]) -> axum::response::Response {
+    let mut body = Vec::new();
+    if let Some(name) = name {
+        body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\n{}\r\n", BOUNDARY, name).as_bytes());
+    }
+    body.extend_from_slice(
+        format!("--{}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"image.png\"\r\nContent-Type: image/png\r\n\r\n", BOUNDARY).as_bytes(),
+    );
+    body.extend_from_slice(image);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+    let request = Request::post(uri)
+        .header("content-type", format!("multipart/form-data; boundary={}", BOUNDARY))
+        .body(Body::from(body))
+        .unwrap();
+    app.oneshot(request).await.unwrap()
+}
+
+/// POST an image as [`post_form`] does, returning the status and the JSON body
+async fn post_image(app: axum::Router, uri: &str, name: Option<&str>, image: &[u8]) -> (StatusCode, Value) {
+    let response = post_form(app, uri, name, image).await;
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn enrolled_person_is_recognized() {
+    let app = app().await;
+
+    let (status, body) = post_image(app.clone(), "/enroll?threshold=0.85", Some("Alice"), &uniform_png(255)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["name"], "Alice");
+
+    let (status, body) = post_image(app, "/recognize?threshold=0.85", None, &uniform_png(255)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let results = body.as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["name"], "Alice");
+    assert_eq!(results[0]["is_match"], true);
+    assert!(results[0]["similarity"].as_f64().unwrap() > 0.99);
+}
+
+#[tokio::test]
+async fn enroll_rejects_an_image_without_faces() {
+    let (status, body) = post_image(app().await, "/enroll", Some("Alice"), &uniform_png(0)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["code"], "NO_FACE");
+    assert_eq!(body["faces"], 0);
+}
+
+#[tokio::test]
+async fn enroll_rejects_an_image_with_multiple_faces() {
+    let (status, body) = post_image(app().await, "/enroll", Some("Alice"), &uniform_png(255)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["code"], "MULTIPLE_FACES");
+    assert_eq!(body["faces"], 2);
+}
+
+#[tokio::test]
+async fn recognize_reports_every_face() {
+    let (status, body) = post_image(app().await, "/recognize", None, &uniform_png(255)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn recognize_returns_no_results_without_faces() {
+    let (status, body) = post_image(app().await, "/recognize", None, &uniform_png(0)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn detect_returns_no_detections_without_faces() {
+    let (status, body) = post_image(app().await, "/detect", None, &uniform_png(0)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn debug_detector_returns_the_image_unannotated_without_faces() {
+    let response = post_form(app().await, "/debug/detector", None, &uniform_png(0)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-faces-detected"], "0");
+    assert_eq!(response.headers()["content-type"], "image/png");
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let image = image::load_from_memory(&bytes).unwrap();
+    assert_eq!(image.to_rgb8(), RgbImage::from_pixel(64, 64, Rgb([0; 3])));
+}
+
+#[tokio::test]
+async fn uploads_over_the_size_limit_are_rejected() {
+    let app = app().await;
+    for (uri, name) in [("/enroll", Some("Alice")), ("/recognize", None)] {
+        let (status, body) = post_image(app.clone(), uri, name, &noise_png()).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}: {}", uri, body);
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+    }
+}
+
+#[tokio::test]
+async fn models_with_another_input_name_are_supported() {
+    let mut config = test_config();
+    config.models.detector.path = stub_models().join("detector_data.onnx");
+    config.models.recognizer.path = stub_models().join("recognizer_data.onnx");
+    let app = app_with(config).await;
+
+    let (status, body) = post_image(app.clone(), "/enroll?threshold=0.85", Some("Alice"), &uniform_png(255)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    let (status, body) = post_image(app, "/recognize", None, &uniform_png(255)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let results = body.as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["name"], "Alice");
+}
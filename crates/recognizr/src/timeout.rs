@@ -0,0 +1,32 @@
+use crate::error::AppError;
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Middleware aborting requests that run longer than `server.request_timeout_secs`.
+///
+/// Model runs happen on the blocking thread pool, so the timeout also fires while one is
+/// in progress. That run can't be interrupted, though: its thread still runs it to
+/// completion and holds its session and inference slot until then.
+pub async fn request_timeout(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(secs) = state.config.server.request_timeout_secs else {
+        return Ok(next.run(request).await);
+    };
+
+    let path = request.uri().path().to_string();
+    tokio::time::timeout(Duration::from_secs(secs), next.run(request))
+        .await
+        .map_err(|_| {
+            tracing::warn!("Request to {} exceeded the {} s timeout", path, secs);
+            AppError::Timeout(format!("Request did not complete within {} seconds", secs))
+        })
+}